@@ -3,41 +3,72 @@
 use aes::Aes256;
 use block_modes::{BlockMode, Cbc};
 use block_modes::block_padding::Pkcs7;
+use std::fmt;
 use std::str;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use flate2::read::ZlibDecoder;
-use super::config::AES_KEY;
-use super::config::AES_IV;
+use super::config::{self, Config};
 use anyhow::Result;
+use crate::logging::Redacted;
 // 类型别名
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 
-pub struct AesPkcs7;
+#[derive(Clone)]
+pub struct AesPkcs7 {
+    key: Vec<u8>,
+    iv: Vec<u8>,
+}
+
+impl fmt::Debug for AesPkcs7 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AesPkcs7")
+            .field("key", &Redacted)
+            .field("iv", &Redacted)
+            .finish()
+    }
+}
+
+impl Default for AesPkcs7 {
+    fn default() -> Self {
+        Self::from_config(&config::get())
+    }
+}
 
 impl AesPkcs7 {
-    pub fn encrypt(content: &[u8]) -> Result<Vec<u8>> {
+    pub fn new(key: impl AsRef<[u8]>, iv: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: key.as_ref().to_vec(),
+            iv: iv.as_ref().to_vec(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.aes_key, &config.aes_iv)
+    }
+
+    pub fn encrypt(&self, content: &[u8]) -> Result<Vec<u8>> {
             let content = zlib_compress(content)?;
-            let cipher = Aes256Cbc::new_from_slices(AES_KEY.as_ref(), AES_IV.as_ref())?;
-            Ok(cipher.encrypt_vec(&*content))
+            let cipher = Aes256Cbc::new_from_slices(&self.key, &self.iv)?;
+            Ok(cipher.encrypt_vec(&content))
     }
 
-    pub fn decrypt(content: &[u8]) -> Result<Vec<u8>> {
-        let cipher = Aes256Cbc::new_from_slices(AES_KEY.as_ref(), AES_IV.as_ref())?;
-        let data =cipher.decrypt_vec(&*content)?;
-        Ok(zlib_uncompress(&*data)?)
+    pub fn decrypt(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let data = self.decrypt_raw(content)?;
+        Ok(zlib_uncompress(&data)?)
+    }
+
+    /// 只做 AES 解密，不解压
+    pub fn decrypt_raw(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let cipher = Aes256Cbc::new_from_slices(&self.key, &self.iv)?;
+        Ok(cipher.decrypt_vec(content)?)
     }
 
     pub fn pkcs7padding(text: &str) -> String {
         let bs = 16;
-        let length = text.len();
-        let bytes_length = text.as_bytes().len();
-        let padding_size = if bytes_length == length { length } else { bytes_length };
-        let padding = bs - padding_size % bs;
-        let padding_text = std::iter::repeat(char::from_u32(padding as u32).unwrap())
-            .take(padding)
-            .collect::<String>();
+        let padding = bs - text.len() % bs;
+        let padding_text = std::iter::repeat_n(char::from_u32(padding as u32).unwrap(), padding).collect::<String>();
         format!("{}{}", text, padding_text)
     }
 
@@ -48,12 +79,12 @@ impl AesPkcs7 {
     }
 }
 
-pub fn zlib_compress(data: &[u8]) -> Result<Vec<u8>> {
+pub fn zlib_compress(data: &[u8]) -> io::Result<Vec<u8>> {
     let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
     encoder.write_all(data)?;
-    Ok(encoder.finish()?)
+    encoder.finish()
 }
-pub fn zlib_uncompress(data: &[u8]) -> Result<Vec<u8>> {
+pub fn zlib_uncompress(data: &[u8]) -> io::Result<Vec<u8>> {
     let mut decoder = ZlibDecoder::new(data);
     let mut decompressed = Vec::new();
     decoder.read_to_end(&mut decompressed)?;
@@ -61,7 +92,7 @@ pub fn zlib_uncompress(data: &[u8]) -> Result<Vec<u8>> {
 }
 
 // 示例用法
-// let aes = AesPkcs7::new("a>32bVP7v<63BVLkY[xM>daZ1s9MBP<R", "d6xHIKq]1J]Dt^ue");
-// let encrypted = aes.encrypt(b"hello world");
-// let decrypted = aes.decrypt(&encrypted);
+// let aes = AesPkcs7::new(&config.aes_key, &config.aes_iv);
+// let encrypted = aes.encrypt(b"hello world")?;
+// let decrypted = aes.decrypt(&encrypted)?;
 
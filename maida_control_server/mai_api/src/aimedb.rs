@@ -1,15 +1,85 @@
-use anyhow::Result;
-use chrono::Local;
-use log::{debug, info};
+//! AimeDB：用机台二维码换取 UID
+//!
+//! AimeDB 响应中 `errorID` 为 0 表示成功。其他取值的含义没有公开资料，
+//! 不做猜测，原样放在 [`AimeDbError::ServerError`] 中返回
+//!
+//! 二维码本身格式不对时不会发出请求，直接返回 [`AimeDbError::InvalidFormat`]；
+//! 超过 [`QR_VALIDITY`] 的二维码同样不会发出请求，直接返回 [`AimeDbError::Expired`]；
+//! 生成时间比当前时间晚 [`QR_CLOCK_SKEW`] 以上的二维码视为伪造，返回 [`AimeDbError::InvalidFormat`]
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use tracing::{debug, info, instrument};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, CONNECTION, USER_AGENT};
-use serde_json::{json, Value};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
 use sha2::{Sha256, Digest};
-use std::time::SystemTime;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use lazy_static::lazy_static;
 use regex::Regex;
-use tokio;
-const CHIP_ID: &str = "A63E-01E68606624";
-const COMMON_KEY: &str = "XcW5FW4cPArBXEk4vzKz3CIrMuA5EVVW";
-const API_URL: &str = "http://ai.sys-allnet.cn/wc_aime/api/get_data";
+use crate::config::{self, Config};
+use crate::logging::{redact, Redacted};
+use crate::utils::china_offset;
+
+/// 机台二维码的有效期
+pub const QR_VALIDITY: Duration = Duration::from_secs(10 * 60);
+/// 机台时钟最多比服务器快多少
+pub const QR_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// `errorID`：成功
+pub const AIMEDB_OK: i64 = 0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AimeDbError {
+    /// 二维码格式不对
+    #[error("invalid QR code")]
+    InvalidFormat,
+    #[error("QR code expired")]
+    Expired,
+    #[error("aimedb transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("cannot decode aimedb response: {0}")]
+    Decode(#[from] serde_json::Error),
+    /// AimeDB 返回了非 0 的 `errorID`
+    #[error("aimedb returned errorID {0}")]
+    ServerError(i64),
+}
+
+impl AimeDbError {
+    /// 给前端用的稳定错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            AimeDbError::InvalidFormat => "invalid_format",
+            AimeDbError::Expired => "expired",
+            AimeDbError::Transport(_) => "transport",
+            AimeDbError::Decode(_) => "decode",
+            AimeDbError::ServerError(_) => "server_error",
+        }
+    }
+
+    /// 把 `errorID` 转换为错误，成功时返回 `None`
+    pub fn from_error_id(error_id: i64) -> Option<Self> {
+        match error_id {
+            AIMEDB_OK => None,
+            code => Some(AimeDbError::ServerError(code)),
+        }
+    }
+}
+
+pub type AimeDbResult<T> = Result<T, AimeDbError>;
+
+/// AimeDB `get_data` 的响应
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AimeDbResponse {
+    #[serde(rename = "errorID")]
+    pub error_id: i64,
+    #[serde(rename = "userID")]
+    pub user_id: i64,
+    pub key: String,
+    pub timestamp: String,
+}
 
 pub fn get_sha256(input_str: &str) -> String {
     let mut hasher = Sha256::new();
@@ -17,88 +87,347 @@ pub fn get_sha256(input_str: &str) -> String {
     format!("{:X}", hasher.finalize())
 }
 
+/// 北京时间的 `YYMMDDhhmmss`，与服务器所在时区无关
 pub fn generate_sega_timestamp() -> String {
-    Local::now().format("%y%m%d%H%M%S").to_string()
+    Utc::now().with_timezone(&china_offset()).format("%y%m%d%H%M%S").to_string()
 }
 
 pub fn calc_sega_aimedb_auth_key(var_string: &str, timestamp: &str, common_key: &str) -> String {
     get_sha256(&format!("{}{}{}", var_string, timestamp, common_key))
 }
 
-pub async fn api_aimedb(qr_code: &str) -> Result<String> {
-    let timestamp = generate_sega_timestamp();
-    let current_key = calc_sega_aimedb_auth_key(CHIP_ID, &timestamp, COMMON_KEY);
+/// AimeDB（二维码换 UID）客户端
+#[derive(Clone)]
+pub struct AimeDbClient {
+    client: reqwest::Client,
+    url: String,
+    chip_id: String,
+    common_key: String,
+}
+
+impl fmt::Debug for AimeDbClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AimeDbClient")
+            .field("url", &self.url)
+            .field("chip_id", &self.chip_id)
+            .field("common_key", &Redacted)
+            .finish_non_exhaustive()
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_CLIENT: RwLock<Option<Arc<AimeDbClient>>> = RwLock::new(None);
+}
+
+/// 自由函数使用的全局客户端，首次使用时按全局配置创建
+pub fn default_aimedb_client() -> AimeDbResult<Arc<AimeDbClient>> {
+    if let Some(client) = DEFAULT_CLIENT.read().unwrap().as_ref() {
+        return Ok(client.clone());
+    }
+    let client = Arc::new(AimeDbClient::from_config(&config::get())?);
+    *DEFAULT_CLIENT.write().unwrap() = Some(client.clone());
+    Ok(client)
+}
+
+/// 丢弃全局客户端，下次使用时按最新配置重新创建
+pub fn reset_default_client() {
+    *DEFAULT_CLIENT.write().unwrap() = None;
+}
+
+impl AimeDbClient {
+    pub fn new(url: impl Into<String>, chip_id: impl Into<String>, common_key: impl Into<String>) -> AimeDbResult<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?,
+            url: url.into(),
+            chip_id: chip_id.into(),
+            common_key: common_key.into(),
+        })
+    }
+
+    pub fn from_config(config: &Config) -> AimeDbResult<Self> {
+        Self::new(&config.aimedb_url, &config.aimedb_chip_id, &config.aimedb_common_key)
+    }
+
+    #[instrument(name = "aimedb", skip_all)]
+    pub async fn api_aimedb(&self, qr_code: &str) -> AimeDbResult<String> {
+        let timestamp = generate_sega_timestamp();
+        let current_key = calc_sega_aimedb_auth_key(&self.chip_id, &timestamp, &self.common_key);
+
+        let payload = json!({
+            "chipID": self.chip_id,
+            "openGameID": "MAID",
+            "key": current_key,
+            "qrCode": qr_code,
+            "timestamp": timestamp
+        });
+
+        debug!(payload = %redact(&payload.to_string()), "aimedb request");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONNECTION, HeaderValue::from_static("Keep-Alive"));
+        headers.insert(USER_AGENT, HeaderValue::from_static("WC_AIME_LIB"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let response = self.client
+            .post(&self.url)
+            .headers(headers)
+            .json(&payload)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.text().await?)
+    }
+
+    pub async fn impl_aimedb(&self, qr_code: &str, is_already_final: bool) -> AimeDbResult<String> {
+        let qr_code_final = if is_already_final {
+            qr_code.to_string()
+        } else {
+            SgwcQrCode::parse(qr_code)?.payload
+        };
+
+        let response = self.api_aimedb(&qr_code_final).await?;
 
-    let payload = json!({
-        "chipID": CHIP_ID,
-        "openGameID": "MAID", 
-        "key": current_key,
-        "qrCode": qr_code,
-        "timestamp": timestamp
-    });
+        debug!(response = %redact(&response), "implAimeDB: got response body");
+        Ok(response)
+    }
 
-    println!("Payload: {}", payload.to_string());
+    pub async fn impl_get_uid(&self, qr_content: &str) -> AimeDbResult<AimeDbResponse> {
+        let qr = SgwcQrCode::parse(qr_content)?;
+        if qr.game_id != "MAID" || qr.is_from_future_at(Utc::now()) {
+            return Err(AimeDbError::InvalidFormat);
+        }
+        if qr.is_expired_at(Utc::now()) {
+            return Err(AimeDbError::Expired);
+        }
+
+        let response = self.impl_aimedb(&qr.payload, true).await?;
+        let result: AimeDbResponse = serde_json::from_str(&response)?;
+        info!(error_id = result.error_id, "QRScan got response");
+        match AimeDbError::from_error_id(result.error_id) {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert(CONNECTION, HeaderValue::from_static("Keep-Alive"));
-    headers.insert(USER_AGENT, HeaderValue::from_static("WC_AIME_LIB"));
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+pub async fn api_aimedb(qr_code: &str) -> AimeDbResult<String> {
+    default_aimedb_client()?.api_aimedb(qr_code).await
+}
 
-    let client = reqwest::Client::new();
-    let response = client
-        .post(API_URL)
-        .headers(headers)
-        .json(&payload)
-        .send()
-        .await?;
+lazy_static! {
+    /// `SGWC` + 4 位游戏 ID + `YYMMDDhhmmss` + 64 位十六进制负载
+    static ref SGWC_QR: Regex = Regex::new(r"SGWC([A-Z0-9]{4})([0-9]{12})([0-9A-F]{64})(?:[^0-9A-F]|$)").unwrap();
+}
 
-    Ok(response.text().await?)
+/// 机台上显示的登录二维码，例如 `SGWCMAID250702201530F50E...`
+///
+/// | 位置 | 内容 |
+/// |---|---|
+/// | `[0..4]` | 固定前缀 `SGWC` |
+/// | `[4..8]` | 游戏 ID，舞萌为 `MAID` |
+/// | `[8..20]` | 生成时间 `YYMMDDhhmmss`（北京时间） |
+/// | `[20..84]` | 提交给 AimeDB 的负载 |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SgwcQrCode {
+    pub game_id: String,
+    pub timestamp: DateTime<FixedOffset>,
+    pub payload: String,
 }
 
-pub fn is_sgwc_format(input_string: &str) -> bool {
-    if input_string.len() != 84
-        || !input_string.starts_with("SGWCMAID") {
-        return false;
+impl SgwcQrCode {
+    /// 从扫码得到的文本中找出二维码，允许前后有空白、换行或 URL 包裹
+    pub fn parse(input: &str) -> AimeDbResult<Self> {
+        let text: String = input.split_whitespace().collect();
+        let captures = SGWC_QR.captures(&text).ok_or(AimeDbError::InvalidFormat)?;
+        let timestamp = NaiveDateTime::parse_from_str(&captures[2], "%y%m%d%H%M%S")
+            .ok()
+            .and_then(|t| china_offset().from_local_datetime(&t).single())
+            .ok_or(AimeDbError::InvalidFormat)?;
+        Ok(Self {
+            game_id: captures[1].to_string(),
+            timestamp,
+            payload: captures[3].to_string(),
+        })
     }
 
-    let re = Regex::new(r"^[0-9A-F]+$").unwrap();
-    re.is_match(&input_string[20..])
+    /// 在 `now` 时是否已超过 [`QR_VALIDITY`]
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        now.signed_duration_since(self.timestamp)
+            .to_std()
+            .is_ok_and(|age| age > QR_VALIDITY)
+    }
+
+    /// 生成时间是否比 `now` 晚 [`QR_CLOCK_SKEW`] 以上
+    pub fn is_from_future_at(&self, now: DateTime<Utc>) -> bool {
+        self.timestamp
+            .signed_duration_since(now)
+            .to_std()
+            .is_ok_and(|ahead| ahead > QR_CLOCK_SKEW)
+    }
 }
 
-pub async fn impl_aimedb(qr_code: &str, is_already_final: bool) -> Result<String> {
-    let qr_code_final = if is_already_final {
-        qr_code.to_string()
-    } else {
-        qr_code[20..].to_string()
-    };
+/// 是否恰好是一个完整的舞萌二维码（不允许包裹）
+pub fn is_sgwc_format(input_string: &str) -> bool {
+    input_string.len() == 84
+        && input_string.starts_with("SGWCMAID")
+        && SgwcQrCode::parse(input_string).is_ok()
+}
 
-    let response = api_aimedb(&qr_code_final).await?;
+pub async fn impl_aimedb(qr_code: &str, is_already_final: bool) -> AimeDbResult<String> {
+    default_aimedb_client()?.impl_aimedb(qr_code, is_already_final).await
+}
 
-    debug!("implAimeDB: Response Body is: {}", response);
-    Ok(response)
+pub async fn impl_get_uid(qr_content: &str) -> AimeDbResult<AimeDbResponse> {
+    default_aimedb_client()?.impl_get_uid(qr_content).await
 }
 
-pub async fn impl_get_uid(qr_content: &str) -> Value {
-    if !is_sgwc_format(qr_content) {
-        return json!({"errorID": 60001});
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockAimeDb;
+
+    const QR: &str = "SGWCMAID250702201530F50EFA944761EEE401D1B86A556603F470377B613DA5A77CEEEA219E978209AE";
+    const REJECTED_PAYLOAD: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+    const UNKNOWN_PAYLOAD: &str = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF";
+    const CHIP_ID: &str = "A63E-01E00000000";
+    const COMMON_KEY: &str = "test-common-key";
+
+    async fn mock_and_client() -> (MockAimeDb, AimeDbClient) {
+        let server = MockAimeDb::builder(CHIP_ID, COMMON_KEY)
+            .user(&QR[20..], 12771153)
+            .error(REJECTED_PAYLOAD, 42)
+            .start()
+            .await
+            .unwrap();
+        let client = AimeDbClient::new(server.url(), CHIP_ID, COMMON_KEY).unwrap();
+        (server, client)
+    }
+
+    /// 用当前时间生成一个还在有效期内的二维码
+    fn fresh(payload: &str) -> String {
+        let now = Utc::now().with_timezone(&china_offset());
+        format!("SGWCMAID{}{payload}", now.format("%y%m%d%H%M%S"))
     }
 
-    match impl_aimedb(qr_content, false).await {
-        Ok(response) => {
-            match serde_json::from_str(&response) {
-                Ok(result) => {
-                    info!("QRScan Got Response {:?}", result);
-                    result
-                }
-                Err(_) => json!({"errorID": 60002})
-            }
+    #[test]
+    fn parse_qr_layout() {
+        let qr = SgwcQrCode::parse(QR).unwrap();
+        assert_eq!(qr.game_id, "MAID");
+        assert_eq!(qr.payload, &QR[20..]);
+        assert_eq!(qr.timestamp.to_rfc3339(), "2025-07-02T20:15:30+08:00");
+
+        let issued = qr.timestamp.with_timezone(&Utc);
+        assert!(!qr.is_expired_at(issued + chrono::Duration::minutes(9)));
+        assert!(qr.is_expired_at(issued + chrono::Duration::minutes(11)));
+        // 机台时钟略快时不算过期
+        assert!(!qr.is_expired_at(issued - chrono::Duration::minutes(1)));
+    }
+
+    #[test]
+    fn qr_time_boundaries() {
+        let qr = SgwcQrCode::parse(QR).unwrap();
+        let issued = qr.timestamp.with_timezone(&Utc);
+        let second = chrono::Duration::seconds(1);
+        let validity = chrono::Duration::from_std(QR_VALIDITY).unwrap();
+        let skew = chrono::Duration::from_std(QR_CLOCK_SKEW).unwrap();
+        assert!(!qr.is_expired_at(issued + validity));
+        assert!(qr.is_expired_at(issued + validity + second));
+        assert!(!qr.is_from_future_at(issued - skew));
+        assert!(qr.is_from_future_at(issued - skew - second));
+        assert!(!qr.is_from_future_at(issued + validity + second));
+    }
+
+    #[test]
+    fn parse_qr_from_wrapped_text() {
+        let expected = SgwcQrCode::parse(QR).unwrap();
+        let wrapped = [
+            format!("  {QR}\n"),
+            format!("{}\n{}", &QR[..40], &QR[40..]),
+            format!("https://wq.sys-all.cn/qrcode/req/{QR}.html?l=1"),
+        ];
+        for input in wrapped {
+            assert_eq!(SgwcQrCode::parse(&input).unwrap(), expected, "{input:?}");
         }
-        Err(_) => json!({"errorID": 60002})
+        assert!(is_sgwc_format(QR));
+        assert!(!is_sgwc_format(&format!(" {QR}")));
     }
-}
 
-#[tokio::test]
-async fn test() {
-    let sgq ="SGWCMAID250702201530F50EFA944761EEE401D1B86A556603F470377B613DA5A77CEEEA219E978209AE";
-    println!("{:?}", impl_get_uid(sgq).await);
-}
\ No newline at end of file
+    #[test]
+    fn parse_qr_rejects_malformed() {
+        for input in [
+            "",
+            "SGWCMAID1234",
+            &QR.to_lowercase(),
+            // 日期不存在
+            "SGWCMAID251332201530F50EFA944761EEE401D1B86A556603F470377B613DA5A77CEEEA219E978209AE",
+            // 负载多一位
+            &format!("{QR}0"),
+        ] {
+            assert!(matches!(SgwcQrCode::parse(input), Err(AimeDbError::InvalidFormat)), "{input:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn qr_to_uid() {
+        let (_server, client) = mock_and_client().await;
+        let result = client.impl_get_uid(&fresh(&QR[20..])).await.unwrap();
+        assert_eq!(result.error_id, AIMEDB_OK);
+        assert_eq!(result.user_id, 12771153);
+    }
+
+    #[tokio::test]
+    async fn server_error_ids_are_kept() {
+        let (_server, client) = mock_and_client().await;
+        let err = client.impl_get_uid(&fresh(REJECTED_PAYLOAD)).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::ServerError(42)), "{err:?}");
+        let err = client.impl_get_uid(&fresh(UNKNOWN_PAYLOAD)).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::ServerError(code) if code == MockAimeDb::ERROR_REJECTED), "{err:?}");
+        assert_eq!(err.code(), "server_error");
+    }
+
+    #[tokio::test]
+    async fn malformed_or_stale_qr_is_not_sent() {
+        let (server, client) = mock_and_client().await;
+        let err = client.impl_get_uid(QR).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::Expired), "{err:?}");
+        let err = client.impl_get_uid(&fresh(&QR[20..]).replace("MAID", "SDEZ")).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::InvalidFormat), "{err:?}");
+        let err = client.impl_get_uid("SGWCMAID1234").await.unwrap_err();
+        assert!(matches!(err, AimeDbError::InvalidFormat), "{err:?}");
+        let err = client.impl_get_uid(&QR.to_lowercase()).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::InvalidFormat), "{err:?}");
+        // 生成时间在几年之后
+        let future = Utc::now().with_timezone(&china_offset()) + chrono::Duration::days(3 * 365);
+        let err = client.impl_get_uid(&format!("SGWCMAID{}{}", future.format("%y%m%d%H%M%S"), &QR[20..])).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::InvalidFormat), "{err:?}");
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn transport_and_decode_errors() {
+        let (server, _) = mock_and_client().await;
+        // 连接不上
+        let client = AimeDbClient::new("http://127.0.0.1:9/wc_aime/api/get_data", CHIP_ID, COMMON_KEY).unwrap();
+        let err = client.impl_get_uid(&fresh(&QR[20..])).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::Transport(_)), "{err:?}");
+        // 非 2xx
+        let client = AimeDbClient::new(server.url().replace("get_data", "not_found"), CHIP_ID, COMMON_KEY).unwrap();
+        let err = client.impl_get_uid(&fresh(&QR[20..])).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::Transport(_)), "{err:?}");
+        // 返回的不是 JSON
+        let client = AimeDbClient::new(server.url().replace("get_data", "garbage"), CHIP_ID, COMMON_KEY).unwrap();
+        let err = client.impl_get_uid(&fresh(&QR[20..])).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::Decode(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn wrong_key_is_rejected_by_server() {
+        let (server, _) = mock_and_client().await;
+        let client = AimeDbClient::new(server.url(), CHIP_ID, "wrong-key").unwrap();
+        let err = client.impl_get_uid(&fresh(&QR[20..])).await.unwrap_err();
+        assert!(matches!(err, AimeDbError::ServerError(code) if code == MockAimeDb::ERROR_REJECTED), "{err:?}");
+        assert_eq!(err.code(), "server_error");
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,32 @@
+//! 离线 mock 标题服务器
+//!
+//! 用法：`mock_title_server <fixtures 目录> [监听地址]`
+//!
+//! 密钥和混淆参数读取与客户端相同的配置（`config.toml` / `MAI_*`），
+//! 启动后把输出的地址设为 `MAI_ENDPOINT` 即可让整个服务连到 mock 上
+
+use std::net::SocketAddr;
+use mai_api::aes_pkcs7::AesPkcs7;
+use mai_api::config::Config;
+use mai_api::mock::MockTitleServer;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    mai_api::init_logger();
+    let mut args = std::env::args().skip(1);
+    let fixtures = args
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("usage: mock_title_server <fixtures dir> [addr]"))?;
+    let addr: SocketAddr = args.next().as_deref().unwrap_or("127.0.0.1:42081").parse()?;
+
+    let config = Config::load()?;
+    let server = MockTitleServer::builder(AesPkcs7::from_config(&config), &config.obfuscate_param)
+        .fixtures_dir(&fixtures)?
+        .start_on(addr)
+        .await?;
+    info!("mock title server listening, set MAI_ENDPOINT={}", server.endpoint());
+
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -1,28 +1,381 @@
-pub const REGION_ID: u32 = 22;
-pub const REGION_NAME: &str = "山东";
-pub const PLACE_ID: u32 = 3490;
-pub const PLACE_NAME: &str = "赛博时空枣庄市中店";
-pub const CLIENT_ID: &str = "A63E01E9564";
-pub const TEST_UID: i32 = 12771153;
-pub const USE_PROXY: bool = false;
-pub const PROXY_URL: &str = "http://100.104.133.113:33080";
-
-pub const LOGIN_BONUS_DB_PATH: &str = "./assets/loginBonusDB.xml";
-pub const MUSIC_DB_PATH: &str = "./assets/music_data.json";
-pub const ALIASES_DB_PATH: &str = "./assets/maimaidxalias.json";
-
-pub const LOGIN_BONUS_DB_PATH_FALLBACK: &str = "./assets/loginBonusDB.xml";
-pub const MUSIC_DB_PATH_FALLBACK: &str = "./assets/musicDB.json";
-pub const FISH_TOKEN: &str =  "DezYcWXl7HBiUjv6abpGV2Cfw39mJTox";
-// pub const AES_KEY:&[u8; 32] = b"a>32bVP7v<63BVLkY[xM>daZ1s9MBP<R";
-// pub const AES_IV: &[u8; 16]= b"d6xHIKq]1J]Dt^ue";
-pub const AES_KEY: &str = "a>32bVP7v<63BVLkY[xM>daZ1s9MBP<R";
-pub const AES_IV:  &str= "d6xHIKq]1J]Dt^ue";
-pub const OBFUSCATE_PARAM: &str = "B44df8yT";
-pub const ENDPOINT: &str = "https://maimai-gm.wahlap.com:42081/Maimai2Servlet/";
+//! 运行时配置
+//!
+//! 配置按以下顺序叠加，后者覆盖前者：
+//! 1. [`Config::default`] 中的默认值
+//! 2. TOML 配置文件（路径由 `MAI_CONFIG` 指定，默认 `./config.toml`）
+//! 3. `MAI_` 前缀的环境变量，例如 `MAI_AES_KEY`、`MAI_PLACE_ID`（会先读取 `.env`）
+//!
+//! 密钥类配置（`aes_key` / `aes_iv` / `obfuscate_param` / `aimedb_common_key`）没有默认值，必须由配置文件或环境变量提供
+
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use crate::logging::Redacted;
 
 pub const COMBO_ID_TO_NAME: [&str; 5] = ["", "fc", "fcp", "ap", "app"];
 pub const SYNC_ID_TO_NAME: [&str; 6] = ["", "fs", "fsp", "fsd", "fsdp", "sync"];
 
+pub const ENV_PREFIX: &str = "MAI_";
+pub const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid config `{key}`: {reason}")]
+    Invalid { key: String, reason: String },
+}
+
+impl ConfigError {
+    fn invalid(key: impl Into<String>, reason: impl Display) -> Self {
+        ConfigError::Invalid {
+            key: key.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub region_id: u32,
+    pub region_name: String,
+    pub place_id: u32,
+    pub place_name: String,
+    /// 机台狗号
+    pub client_id: String,
+    /// 测试用 UID，仅测试代码使用
+    pub test_uid: Option<i32>,
+
+    /// 标题服务器地址，必须以 `/` 结尾
+    pub endpoint: String,
+    /// AES-256-CBC 密钥，32 字节
+    pub aes_key: String,
+    /// AES-256-CBC IV，16 字节
+    pub aes_iv: String,
+    /// API 路径哈希用的混淆参数
+    pub obfuscate_param: String,
+    /// 标题服务器请求超时（秒）
+    pub timeout_secs: u64,
+    pub use_proxy: bool,
+    pub proxy_url: String,
+
+    /// AimeDB 二维码换 UID 接口
+    pub aimedb_url: String,
+    pub aimedb_chip_id: String,
+    /// AimeDB 请求签名用的公共密钥
+    pub aimedb_common_key: String,
+
+    pub login_bonus_db_path: String,
+    pub music_db_path: String,
+    pub aliases_db_path: String,
+    pub login_bonus_db_path_fallback: String,
+    pub music_db_path_fallback: String,
+    pub fish_token: String,
+
+    /// 数据库地址，`sqlite://...` 或 `mysql://...`
+    pub database_url: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            region_id: 22,
+            region_name: "山东".to_string(),
+            place_id: 3490,
+            place_name: "赛博时空枣庄市中店".to_string(),
+            client_id: "A63E01E9564".to_string(),
+            test_uid: None,
+
+            endpoint: "https://maimai-gm.wahlap.com:42081/Maimai2Servlet/".to_string(),
+            aes_key: String::new(),
+            aes_iv: String::new(),
+            obfuscate_param: String::new(),
+            timeout_secs: 10,
+            use_proxy: false,
+            proxy_url: String::new(),
+
+            aimedb_url: "http://ai.sys-allnet.cn/wc_aime/api/get_data".to_string(),
+            aimedb_chip_id: "A63E-01E68606624".to_string(),
+            aimedb_common_key: String::new(),
+
+            login_bonus_db_path: "./assets/loginBonusDB.xml".to_string(),
+            music_db_path: "./assets/music_data.json".to_string(),
+            aliases_db_path: "./assets/maimaidxalias.json".to_string(),
+            login_bonus_db_path_fallback: "./assets/loginBonusDB.xml".to_string(),
+            music_db_path_fallback: "./assets/musicDB.json".to_string(),
+            fish_token: String::new(),
+
+            database_url: "sqlite://./maida.db?mode=rwc".to_string(),
+        }
+    }
+}
+
+/// 密钥类字段输出为 `<redacted>`
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("region_id", &self.region_id)
+            .field("region_name", &self.region_name)
+            .field("place_id", &self.place_id)
+            .field("place_name", &self.place_name)
+            .field("client_id", &self.client_id)
+            .field("test_uid", &self.test_uid)
+            .field("endpoint", &self.endpoint)
+            .field("aes_key", &Redacted)
+            .field("aes_iv", &Redacted)
+            .field("obfuscate_param", &Redacted)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("use_proxy", &self.use_proxy)
+            .field("proxy_url", &self.proxy_url)
+            .field("aimedb_url", &self.aimedb_url)
+            .field("aimedb_chip_id", &self.aimedb_chip_id)
+            .field("aimedb_common_key", &Redacted)
+            .field("login_bonus_db_path", &self.login_bonus_db_path)
+            .field("music_db_path", &self.music_db_path)
+            .field("aliases_db_path", &self.aliases_db_path)
+            .field("login_bonus_db_path_fallback", &self.login_bonus_db_path_fallback)
+            .field("music_db_path_fallback", &self.music_db_path_fallback)
+            .field("fish_token", &Redacted)
+            .field("database_url", &self.database_url)
+            .finish()
+    }
+}
+
+/// 读取 `MAI_<KEY>` 环境变量并解析，返回的错误中带有环境变量名
+fn env_value<T>(key: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let name = format!("{ENV_PREFIX}{}", key.to_uppercase());
+    match env::var(&name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| ConfigError::invalid(name, e)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(ConfigError::invalid(name, e)),
+    }
+}
+
+macro_rules! apply_env {
+    ($config:ident, $( $field:ident )*) => {
+        $(
+            if let Some(value) = env_value(stringify!($field))? {
+                $config.$field = value;
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// 按默认路径加载配置文件并叠加环境变量
+    ///
+    /// 未设置 `MAI_CONFIG` 且 `./config.toml` 不存在时只使用默认值和环境变量
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+        let config = match env::var(format!("{ENV_PREFIX}CONFIG")) {
+            Ok(path) => Self::from_file(path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(DEFAULT_CONFIG_PATH)?,
+            Err(_) => Self::default(),
+        };
+        config.with_env()
+    }
+
+    /// 只读取配置文件，不叠加环境变量，也不做校验
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml(&text).map_err(|e| match e {
+            ConfigError::Parse { source, .. } => ConfigError::Parse {
+                path: path.to_path_buf(),
+                source,
+            },
+            e => e,
+        })
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(|source| ConfigError::Parse {
+            path: PathBuf::new(),
+            source,
+        })
+    }
+
+    /// 用 `MAI_` 环境变量覆盖当前配置，然后校验
+    pub fn with_env(mut self) -> Result<Self, ConfigError> {
+        let config = &mut self;
+        apply_env!(config,
+            region_id region_name place_id place_name client_id
+            endpoint aes_key aes_iv obfuscate_param timeout_secs use_proxy proxy_url
+            aimedb_url aimedb_chip_id aimedb_common_key
+            login_bonus_db_path music_db_path aliases_db_path
+            login_bonus_db_path_fallback music_db_path_fallback fish_token
+            database_url
+        );
+        if let Some(test_uid) = env_value("test_uid")? {
+            config.test_uid = Some(test_uid);
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.aes_key.len() != 32 {
+            return Err(ConfigError::invalid("aes_key", format!("must be 32 bytes, got {}", self.aes_key.len())));
+        }
+        if self.aes_iv.len() != 16 {
+            return Err(ConfigError::invalid("aes_iv", format!("must be 16 bytes, got {}", self.aes_iv.len())));
+        }
+        if self.obfuscate_param.is_empty() {
+            return Err(ConfigError::invalid("obfuscate_param", "must not be empty"));
+        }
+        if !(self.endpoint.starts_with("http://") || self.endpoint.starts_with("https://")) {
+            return Err(ConfigError::invalid("endpoint", "must start with http:// or https://"));
+        }
+        if !self.endpoint.ends_with('/') {
+            return Err(ConfigError::invalid("endpoint", "must end with `/`"));
+        }
+        if self.client_id.is_empty() {
+            return Err(ConfigError::invalid("client_id", "must not be empty"));
+        }
+        if self.timeout_secs == 0 {
+            return Err(ConfigError::invalid("timeout_secs", "must be greater than 0"));
+        }
+        if self.use_proxy && self.proxy_url.is_empty() {
+            return Err(ConfigError::invalid("proxy_url", "must be set when use_proxy = true"));
+        }
+        if !(self.aimedb_url.starts_with("http://") || self.aimedb_url.starts_with("https://")) {
+            return Err(ConfigError::invalid("aimedb_url", "must start with http:// or https://"));
+        }
+        if self.aimedb_common_key.is_empty() {
+            return Err(ConfigError::invalid("aimedb_common_key", "must not be empty"));
+        }
+        if !(self.database_url.starts_with("sqlite:") || self.database_url.starts_with("mysql://")) {
+            return Err(ConfigError::invalid("database_url", "must start with sqlite: or mysql://"));
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+}
+
+/// 设置全局配置，之后新建的默认客户端都会使用它
+pub fn init(config: Config) {
+    *CONFIG.write().unwrap() = Some(Arc::new(config));
+    crate::title_server::reset_default_client();
+    crate::aimedb::reset_default_client();
+}
+
+/// 获取全局配置，未调用 [`init`] 时会用 [`Config::load`] 加载一次
+///
+/// # Panics
+/// 配置无效时 panic，服务端应在启动时调用 [`Config::load`] 和 [`init`] 以便提前报错
+pub fn get() -> Arc<Config> {
+    if let Some(config) = CONFIG.read().unwrap().as_ref() {
+        return config.clone();
+    }
+    let mut guard = CONFIG.write().unwrap();
+    guard
+        .get_or_insert_with(|| {
+            Arc::new(Config::load().unwrap_or_else(|e| panic!("mai_api config: {e}")))
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> Config {
+        Config {
+            aes_key: "0".repeat(32),
+            aes_iv: "0".repeat(16),
+            obfuscate_param: "salt".to_string(),
+            aimedb_common_key: "key".to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn parse_toml() {
+        let config = Config::from_toml(
+            r#"
+            place_id = 1
+            aes_key = "0123456789abcdef0123456789abcdef"
+            aes_iv = "0123456789abcdef"
+            obfuscate_param = "salt"
+            aimedb_common_key = "key"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.place_id, 1);
+        assert_eq!(config.region_id, Config::default().region_id);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        assert!(matches!(Config::from_toml("aes_kye = \"x\""), Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn validation_names_bad_key() {
+        let config = Config { aes_iv: "short".to_string(), ..valid() };
+        match config.validate() {
+            Err(ConfigError::Invalid { key, .. }) => assert_eq!(key, "aes_iv"),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let config = Config { endpoint: "https://example.com".to_string(), ..valid() };
+        match config.validate() {
+            Err(ConfigError::Invalid { key, .. }) => assert_eq!(key, "endpoint"),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let config = Config { database_url: "postgres://localhost/maida".to_string(), ..valid() };
+        match config.validate() {
+            Err(ConfigError::Invalid { key, .. }) => assert_eq!(key, "database_url"),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn debug_hides_secrets() {
+        let config = Config {
+            aes_key: "K".repeat(32),
+            aes_iv: "I".repeat(16),
+            obfuscate_param: "obfuscate-salt".to_string(),
+            aimedb_common_key: "common-key".to_string(),
+            ..Config::default()
+        };
+        let text = format!("{config:?}");
+        for secret in ["KKKK", "IIII", "obfuscate-salt", "common-key"] {
+            assert!(!text.contains(secret), "{text}");
+        }
+        assert!(text.contains("place_id: 3490"));
 
-// 日本精工，安全防漏
\ No newline at end of file
+        let cipher = format!("{:?}", crate::aes_pkcs7::AesPkcs7::from_config(&config));
+        assert_eq!(cipher, "AesPkcs7 { key: <redacted>, iv: <redacted> }");
+    }
+}
@@ -0,0 +1,39 @@
+use sea_orm::entity::prelude::*;
+
+/// 每张谱面当前的最好成绩，`(level_index, song_id, uid)` 唯一
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "achievements")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub song_id: i32,
+    /// 达成率 ×10000
+    pub achievements: i32,
+    pub uid: i32,
+    pub dx_score: i32,
+    pub level_index: i32,
+    pub fc: String,
+    pub fs: String,
+    pub ra: i32,
+    #[sea_orm(column_type = "Float")]
+    pub constant: f32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::Uid",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
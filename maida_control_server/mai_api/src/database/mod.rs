@@ -0,0 +1,43 @@
+//! 数据库实体
+//!
+//! 表结构由 `migration` crate 维护，支持 MySQL（`mysql://...`）和 SQLite（`sqlite://...`）
+
+pub mod prelude;
+
+pub mod achievements;
+pub mod score_history;
+pub mod user;
+
+use anyhow::Result;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::Database;
+pub use sea_orm::DatabaseConnection;
+
+/// 连接数据库并执行尚未执行的迁移
+pub async fn connect(url: &str) -> Result<DatabaseConnection> {
+    let db = Database::connect(url).await?;
+    Migrator::up(&db, None).await?;
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+    use super::*;
+
+    #[tokio::test]
+    async fn migrations_round_trip() -> Result<()> {
+        let db = connect("sqlite::memory:").await?;
+        assert_eq!(Migrator::get_applied_migrations(&db).await?.len(), 3);
+        // 重复执行不会出错
+        Migrator::up(&db, None).await?;
+
+        let user = user::ActiveModel { qq: Set(10001), ..Default::default() }.insert(&db).await?;
+        assert_eq!(prelude::User::find_by_id(user.id).one(&db).await?, Some(user));
+
+        Migrator::down(&db, None).await?;
+        assert_eq!(Migrator::get_pending_migrations(&db).await?.len(), 3);
+        Migrator::up(&db, None).await?;
+        Ok(())
+    }
+}
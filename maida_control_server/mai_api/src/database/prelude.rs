@@ -0,0 +1,3 @@
+pub use super::achievements::Entity as Achievements;
+pub use super::score_history::Entity as ScoreHistory;
+pub use super::user::Entity as User;
@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+
+/// 只追加的成绩历史，见 [`crate::score_history`]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "score_history")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub uid: i32,
+    pub song_id: i32,
+    pub level_index: i32,
+    pub achievements: i32,
+    pub dx_score: i32,
+    pub fc: String,
+    pub fs: String,
+    pub observed_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::Uid",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "user")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    pub qq: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::achievements::Entity")]
+    Achievements,
+    #[sea_orm(has_many = "super::score_history::Entity")]
+    ScoreHistory,
+}
+
+impl Related<super::achievements::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Achievements.def()
+    }
+}
+
+impl Related<super::score_history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ScoreHistory.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
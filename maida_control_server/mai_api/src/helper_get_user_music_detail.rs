@@ -1,45 +1,54 @@
 use anyhow::Result;
 use serde_json::{self, json};
-use log::info;
+use tracing::{debug, warn};
 use sea_orm::prelude::*;
-use crate::config::{COMBO_ID_TO_NAME, SYNC_ID_TO_NAME, TEST_UID};
+use crate::config::{COMBO_ID_TO_NAME, SYNC_ID_TO_NAME};
 use crate::database::{achievements, user};
-use crate::mai_api::music_data::{get_music_data, get_music_title};
-use super::{api_sbga, get_user_music_api};
-
-
-pub async fn get_user_music_detail(user_id: i32, next_index: i32, max_count: i32) -> Result<serde_json::Value> {
-    let data = json!({
-        "userId": user_id,
-        "nextIndex": next_index,
-        "maxCount": max_count
-    });
-    let response = get_user_music_api(data, user_id.to_string()).await?;
-    Ok(serde_json::from_str(&response)?)
+use crate::music_data::{get_music_data, get_music_title};
+use super::{default_client, TitleServerClient, UserMusicDetail, UserMusicResponse, UserPagedRequest};
+
+
+pub async fn get_user_music_detail(user_id: i32, next_index: i64, max_count: i64) -> Result<UserMusicResponse> {
+    get_user_music_detail_with(&*default_client()?, user_id, next_index, max_count).await
+}
+
+pub async fn get_user_music_detail_with(
+    client: &TitleServerClient,
+    user_id: i32,
+    next_index: i64,
+    max_count: i64,
+) -> Result<UserMusicResponse> {
+    let data = UserPagedRequest {
+        user_id: user_id as i64,
+        next_index,
+        max_count,
+    };
+    Ok(client.get_user_music_api(&data, user_id.to_string()).await?)
 }
 
-pub async fn get_user_full_music_detail(user_id: i32) -> Result<Vec<serde_json::Value>> {
+pub async fn get_user_full_music_detail(user_id: i32) -> Result<Vec<UserMusicDetail>> {
+    get_user_full_music_detail_with(&*default_client()?, user_id).await
+}
+
+/// 翻页拉取全部游玩过的谱面
+pub async fn get_user_full_music_detail_with(client: &TitleServerClient, user_id: i32) -> Result<Vec<UserMusicDetail>> {
     let mut current_user_music_detail_list = Vec::new();
     let mut next_index = 0;
 
     loop {
-        let user_music_response = get_user_music_detail(user_id, next_index, 50).await?;
+        let user_music_response = get_user_music_detail_with(client, user_id, next_index, 50).await?;
 
-        next_index = user_music_response["nextIndex"].as_i64().map(|x| x as i32).expect("REASON");
-        println!("NextIndex: {:?}", next_index);
+        next_index = user_music_response.next_index;
+        debug!(next_index, "fetched user music page");
 
-        if user_music_response["userMusicList"].as_array().map_or(true, |x| x.is_empty()) {
+        if user_music_response.user_music_list.is_empty() {
             break;
         }
 
-        if let Some(music_list) = user_music_response["userMusicList"].as_array() {
-            for current_music in music_list {
-                if let Some(detail_list) = current_music["userMusicDetailList"].as_array() {
-                    for detail in detail_list {
-                        if detail["playCount"].as_i64().unwrap_or(0) > 0 {
-                            current_user_music_detail_list.push(detail.clone());
-                        }
-                    }
+        for current_music in user_music_response.user_music_list {
+            for detail in current_music.user_music_detail_list {
+                if detail.play_count > 0 {
+                    current_user_music_detail_list.push(detail);
                 }
             }
         }
@@ -49,47 +58,50 @@ pub async fn get_user_full_music_detail(user_id: i32) -> Result<Vec<serde_json::
     Ok(current_user_music_detail_list)
 }
 
-use sea_orm::{sea_query, DatabaseConnection, EntityTrait, Set, TryInsert};
+use sea_orm::{DatabaseConnection, EntityTrait, Set, TransactionTrait};
 use sea_orm::sea_query::OnConflict;
-use serde_json::Value;
 use crate::database::prelude::User;
-use crate::mai_api::utils::single_ra;
+use crate::utils::{level_index, single_ra};
+use crate::score_history::record_scores;
+use chrono::Utc;
 
 pub async fn upsert_user_music_detail(
     user_qq: i64,
-    songs: Vec<Value>,
+    songs: Vec<UserMusicDetail>,
     db: &DatabaseConnection,
 ) -> Result<()> {
 
-    let user = User::find().filter(user::Column::Qq.eq(user_qq)).one(&*db).await?;
+    let user = User::find().filter(user::Column::Qq.eq(user_qq)).one(db).await?;
     if user.is_none() { return Err(anyhow::anyhow!("user not found")) }
     let user = user.unwrap();
+    // 历史和最好成绩一起提交，中途失败时两边都不写入
+    let txn = db.begin().await?;
+    record_scores(&txn, user.id, &songs, Utc::now()).await?;
     let mut achievements=Vec::new();
     for song in songs {
 
-        let song_id = song.get("musicId").unwrap().as_i64().unwrap() as i32;
+        let song_id = song.music_id;
         if let Some(_song) = get_music_data(song_id){
-            println!("{song:?}");
+            debug!(?song, "upsert achievement");
             // 创建新的 ActiveModel 对象
-            let achieve = song["achievement"].as_i64().unwrap_or(0) as i32;
-            let mut level = song["level"].as_i64().unwrap_or(0) as i32;
-            if level==10 { level=0 }
+            let achieve = song.achievement;
+            let level = level_index(song.level);
             let constant = _song.ds[level as usize];
             let achievement = achievements::ActiveModel {
                 id: sea_orm::NotSet, // id 为空表示这是插入操作
                 song_id: sea_orm::Set(song_id),
-                achievements: sea_orm::Set(achieve.clone()),
+                achievements: sea_orm::Set(achieve),
                 uid: sea_orm::Set(user.id),
-                dx_score: sea_orm::Set(song["deluxscoreMax"].as_i64().unwrap_or(0) as i32),
+                dx_score: sea_orm::Set(song.deluxscore_max),
                 level_index: sea_orm::Set(level),
-                fc: sea_orm::Set(COMBO_ID_TO_NAME[song["comboStatus"].as_i64().unwrap() as usize].to_string()),
-                fs: sea_orm::Set(SYNC_ID_TO_NAME[song["syncStatus"].as_i64().unwrap() as usize].to_string()),
+                fc: sea_orm::Set(COMBO_ID_TO_NAME.get(song.combo_status as usize).copied().unwrap_or_default().to_string()),
+                fs: sea_orm::Set(SYNC_ID_TO_NAME.get(song.sync_status as usize).copied().unwrap_or_default().to_string()),
                 ra:Set(single_ra(achieve,constant)),
                 constant: Set(constant),
             };
             achievements.push(achievement);
         }else{
-            println!("unknow song {song_id:?}");
+            warn!(song_id, "unknown song");
         }
 
     }
@@ -111,24 +123,24 @@ pub async fn upsert_user_music_detail(
     let result = achievements::Entity::insert_many(achievements)
         .on_conflict(conflict)
         .do_nothing()
-        .exec(db)
+        .exec(&txn)
         .await?;
-    println!("{result:?}");
+    txn.commit().await?;
+    debug!(?result, "upserted achievements");
     Ok(())
 }
 
 
-pub async fn parse_user_full_music_detail(user_full_music_detail_list: Vec<serde_json::Value>) -> Result<Vec<serde_json::Value>> {
+pub async fn parse_user_full_music_detail(user_full_music_detail_list: Vec<UserMusicDetail>) -> Result<Vec<serde_json::Value>> {
     let mut music_detail_list = Vec::new();
 
     for detail in user_full_music_detail_list {
-        println!("{detail}");
         music_detail_list.push(json!({
-            "id": detail["musicId"].as_i64().unwrap_or(0) as i32,
-            "歌名": get_music_title(detail["musicId"].as_i64().unwrap_or(0) as i32).unwrap_or("？？？".to_string()),
-            "难度": detail["level"],
-            "分数": detail["achievement"].as_f64().unwrap_or(0.0) / 10000.0,
-            "DX分数": detail["deluxscoreMax"]
+            "id": detail.music_id,
+            "歌名": get_music_title(detail.music_id).unwrap_or("？？？".to_string()),
+            "难度": detail.level,
+            "分数": detail.achievement as f64 / 10000.0,
+            "DX分数": detail.deluxscore_max
         }));
     }
 
@@ -137,17 +149,25 @@ pub async fn parse_user_full_music_detail(user_full_music_detail_list: Vec<serde
 
 #[cfg(test)]
 mod tests {
-    use kovi::tokio;
-    use crate::mai_api::music_data::load_music_data;
+    use crate::mock::{test_cipher, MockTitleServer};
     use super::*;
 
     #[tokio::test]
     async fn test_get_user_music_detail() -> Result<()> {
-        load_music_data().ok();
-        let user_full_music_detail_list = get_user_full_music_detail(TEST_UID).await?;
-        println!("{:?}", user_full_music_detail_list.len());
-        let parsed_detail = parse_user_full_music_detail(user_full_music_detail_list).await?;
-        println!("{:?}", parsed_detail);
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .fixture("GetUserMusicApi", serde_json::from_str(include_str!("../tests/fixtures/title_server/GetUserMusicApi.json"))?)
+            .start()
+            .await?;
+        let client = server.client_builder().build()?;
+
+        let user_full_music_detail_list = get_user_full_music_detail_with(&client, 12771153).await?;
+        // 第二页中 playCount 为 0 的谱面会被过滤掉
+        assert_eq!(user_full_music_detail_list.len(), 4);
+        let next_indexes: Vec<_> = server.requests().iter().map(|r| r.body["nextIndex"].clone()).collect();
+        assert_eq!(next_indexes, vec![json!(0), json!(10000000002_i64)]);
+        assert_eq!(user_full_music_detail_list[0].music_id, 11663);
+        assert_eq!(user_full_music_detail_list[0].achievement, 1005000);
+        assert_eq!(user_full_music_detail_list[3].music_id, 11311);
         Ok(())
     }
-}
\ No newline at end of file
+}
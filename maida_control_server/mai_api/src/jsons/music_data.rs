@@ -0,0 +1,32 @@
+//! `music_data.json`（水鱼查分器乐曲数据）的结构，只保留用到的字段
+
+use std::collections::HashMap;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Song {
+    pub id: String,
+    pub title: String,
+    /// `SD` 或 `DX`
+    #[serde(rename = "type")]
+    pub song_type: String,
+    /// 各难度定数，Basic 起
+    pub ds: Vec<f32>,
+    /// 各难度等级，如 `13+`
+    pub level: Vec<String>,
+    pub basic_info: BasicInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicInfo {
+    pub title: String,
+    pub artist: String,
+    pub genre: String,
+    /// 收录版本
+    pub from: String,
+    /// 是否为当前版本曲目（计入 B15）
+    pub is_new: bool,
+}
+
+/// 乐曲 ID → 乐曲
+pub type MusicData = HashMap<i32, Song>;
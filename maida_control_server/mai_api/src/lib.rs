@@ -1,7 +1,6 @@
 pub mod helper_get_user_thing;
 
-use serde_json::json;
-pub use helper_get_user_thing::*;
+use serde_json::Value;
 pub mod title_server;
 pub mod config;
 pub mod aes_pkcs7;
@@ -10,30 +9,55 @@ pub mod aimedb;
 pub mod helper_get_user_music_detail;
 pub mod utils;
 pub mod music_data;
+pub mod rating;
+pub mod score_history;
+pub mod vo;
+pub mod database;
+pub mod jsons;
+pub mod logging;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 
-use anyhow::Result;
 pub use title_server::*;
-use tokio;
-use log::{debug, info, warn};
-use aes_pkcs7::AesPkcs7;
-// macro_rules! generate_api_call {
-//     ($( $api_name:ident ),*) => {
-//         $(
-//             pub async fn $api_name(data: serde_json::Value) -> Result<String> {
-//                 api_sbga(data, stringify!($api_name), &config::TEST_UID.to_string()).await
-//             }
-//         )*
-//     };
-// }
+pub use vo::*;
+pub use logging::init_logger;
+/// 接口是否可以安全重试，由 `generate_api_call!` 中的 `#[idempotent]` 标记
+macro_rules! is_idempotent {
+    (idempotent) => {
+        true
+    };
+}
+
+/// 为每个接口生成类型化的调用函数，标记了 `#[idempotent]` 的读取接口失败时按重试策略重试，其余只请求一次
 macro_rules! generate_api_call {
-    ($( $api_name:ident )*) => {
+    ($( $(#[$flag:ident])? $api_name:ident ( $req:ty ) -> $res:ty ; )*) => {
+        /// 原始版本：直接返回解密后的 JSON 字符串，调试时使用
+        pub mod raw {
+            use super::*;
+            $(
+                // 将 snake_case 转为 camelCase
+                pub async fn $api_name(data: serde_json::Value,user_agent_extra_data:String) -> TitleServerResult<String> {
+                    let camel_case_name = snake_to_pascal(stringify!($api_name));
+                    let idempotent = false $(|| is_idempotent!($flag))?;
+                    default_client()?.call_with(data, &camel_case_name, user_agent_extra_data, idempotent).await
+                }
+            )*
+        }
         $(
-            // 将 snake_case 转为 camelCase
-            pub async fn $api_name(data: serde_json::Value,user_agent_extra_data:String) -> Result<String> {
-                let camel_case_name = snake_to_pascal(stringify!($api_name));
-                api_sbga(data, &camel_case_name, user_agent_extra_data).await
+            pub async fn $api_name(data: &$req, user_agent_extra_data:String) -> TitleServerResult<$res> {
+                default_client()?.$api_name(data, user_agent_extra_data).await
             }
         )*
+        impl TitleServerClient {
+            $(
+                pub async fn $api_name(&self, data: &$req, user_agent_extra_data:String) -> TitleServerResult<$res> {
+                    let data = serde_json::to_value(data).map_err(|e| TitleServerError::Encode(e.to_string()))?;
+                    let idempotent = false $(|| is_idempotent!($flag))?;
+                    let response = self.call_with(data, &snake_to_pascal(stringify!($api_name)), user_agent_extra_data, idempotent).await?;
+                    Ok(serde_json::from_str(&response)?)
+                }
+            )*
+        }
     };
 }
 
@@ -47,7 +71,7 @@ fn snake_to_pascal(s: &str) -> String {
             continue;
         }
         if capitalize {
-            result.push(c.to_uppercase().next().unwrap());
+            result.extend(c.to_uppercase());
             capitalize = false;
         } else {
             result.push(c);
@@ -58,68 +82,127 @@ fn snake_to_pascal(s: &str) -> String {
 }
 
 generate_api_call! {
-    get_user_card_api
-    get_user_character_api
-    get_user_charge_api
-    get_user_course_api
-    get_user_data_api
-    get_user_extend_api
-    get_user_favorite_api
-    get_user_friend_season_ranking_api
-    get_user_ghost_api
-    get_user_item_api
-    get_game_charge_api
-    get_user_login_bonus_api
-    get_game_event_api
-    get_user_map_api
-    get_game_ng_music_id_api
-    get_user_music_api
-    get_game_ranking_api
-    get_game_setting_api
-    get_user_option_api
-    get_user_portrait_api
-    get_game_tournament_info_api
-    user_logout_api
-    get_user_preview_api
-    get_transfer_friend_api
-    get_user_rating_api
-    get_user_activity_api
-    get_user_recommend_rate_music_api
-    get_user_recommend_select_music_api
-    get_user_region_api
-    get_user_score_ranking_api
-    upload_user_photo_api
-    upload_user_playlog_api
-    upload_user_portrait_api
-    upsert_client_bookkeeping_api
-    upsert_client_setting_api
-    upsert_client_testmode_api
-    upsert_client_upload_api
-    upsert_user_all_api
-    upsert_user_chargelog_api
-    user_login_api
-    ping
-    get_user_favorite_item_api
-    get_game_ng_word_list_api
+    // 有类型的接口：本项目用到的，以及字段含义明确的登录、登出、卡片、设置接口
+    #[idempotent] get_user_card_api(UserPagedRequest) -> UserCardResponse;
+    #[idempotent] get_user_data_api(UserIdRequest) -> UserDataResponse;
+    #[idempotent] get_user_favorite_api(UserFavoriteRequest) -> UserFavoriteResponse;
+    #[idempotent] get_user_music_api(UserPagedRequest) -> UserMusicResponse;
+    #[idempotent] get_user_option_api(UserIdRequest) -> UserOptionResponse;
+    #[idempotent] get_user_preview_api(UserIdRequest) -> UserPreview;
+    #[idempotent] get_user_rating_api(UserIdRequest) -> UserRatingResponse;
+    user_login_api(UserLoginRequest) -> UserLoginResponse;
+    user_logout_api(UserLogoutRequest) -> ReturnCodeResponse;
+    upsert_user_all_api(Value) -> ReturnCodeResponse;
+
+    // 故意保留 `Value` 的接口：本项目不调用，手上也没有真实的请求 / 响应样本，
+    // 猜出来的结构体只会在字段不符时反序列化失败，不如让调用方自己构造和解析。
+    // 需要使用时先抓一份真实响应放进 `tests/fixtures/title_server/`，再改成有类型的版本。
+    // `upsert_user_all_api` 的请求体是整份用户数据，同样由调用方构造
+    // 其他用户数据读取
+    #[idempotent] get_user_character_api(Value) -> Value;
+    #[idempotent] get_user_charge_api(Value) -> Value;
+    #[idempotent] get_user_course_api(Value) -> Value;
+    #[idempotent] get_user_extend_api(Value) -> Value;
+    #[idempotent] get_user_friend_season_ranking_api(Value) -> Value;
+    #[idempotent] get_user_ghost_api(Value) -> Value;
+    #[idempotent] get_user_item_api(Value) -> Value;
+    #[idempotent] get_user_login_bonus_api(Value) -> Value;
+    #[idempotent] get_user_map_api(Value) -> Value;
+    #[idempotent] get_user_portrait_api(Value) -> Value;
+    #[idempotent] get_transfer_friend_api(Value) -> Value;
+    #[idempotent] get_user_activity_api(Value) -> Value;
+    #[idempotent] get_user_recommend_rate_music_api(Value) -> Value;
+    #[idempotent] get_user_recommend_select_music_api(Value) -> Value;
+    #[idempotent] get_user_region_api(Value) -> Value;
+    #[idempotent] get_user_score_ranking_api(Value) -> Value;
+    #[idempotent] get_user_favorite_item_api(Value) -> Value;
+    // 机台启动时读取的游戏设置
+    #[idempotent] get_game_charge_api(Value) -> Value;
+    #[idempotent] get_game_event_api(Value) -> Value;
+    #[idempotent] get_game_ng_music_id_api(Value) -> Value;
+    #[idempotent] get_game_ranking_api(Value) -> Value;
+    #[idempotent] get_game_setting_api(Value) -> Value;
+    #[idempotent] get_game_tournament_info_api(Value) -> Value;
+    #[idempotent] get_game_ng_word_list_api(Value) -> Value;
+    #[idempotent] ping(Value) -> Value;
+    // 机台上传与记账
+    upload_user_photo_api(Value) -> Value;
+    upload_user_playlog_api(Value) -> Value;
+    upload_user_portrait_api(Value) -> Value;
+    upsert_client_bookkeeping_api(Value) -> Value;
+    upsert_client_setting_api(Value) -> Value;
+    upsert_client_testmode_api(Value) -> Value;
+    upsert_client_upload_api(Value) -> Value;
+    upsert_user_chargelog_api(Value) -> Value;
 }
 
 #[tokio::test]
-async fn test() ->Result<()>{
+async fn test() -> anyhow::Result<()>{
+    use mock::{test_cipher, MockTitleServer};
 
-    let data = json!({
-        "userId":config::TEST_UID
-    });
+    let server = MockTitleServer::builder(test_cipher(), "salt")
+        .fixture("GetUserPreviewApi", serde_json::from_str(include_str!("../tests/fixtures/title_server/GetUserPreviewApi.json"))?)
+        .start()
+        .await?;
+    let client = server.client_builder().build()?;
 
-    let response = get_user_preview_api(data,config::TEST_UID.to_string()).await;
+    let data = UserIdRequest::new(12771153);
+    let response = client.get_user_preview_api(&data, "12771153".to_string()).await?;
+    assert_eq!(response.user_id, 12771153);
+    assert_eq!(response.player_rating, 15234);
+    assert_eq!(response.last_rom_version, "1.50.01");
+    Ok(())
+}
 
-    // println!("{:?}",response)
-    match response {
-        Ok(response_data) => {
-            println!("success:{}",response_data)
-        },
-        Err(e) => {
-            println!("Request failed: {:?}", e);
-        }
-    }
+#[tokio::test]
+async fn typed_login_logout_and_upsert() -> anyhow::Result<()> {
+    use mock::{test_cipher, MockTitleServer};
+    use serde_json::json;
+
+    let server = MockTitleServer::builder(test_cipher(), "salt")
+        .fixture("UserLoginApi", json!({"returnCode": 1, "lastLoginDate": "2025-07-01 20:00:00", "loginCount": 42, "consecutiveLoginCount": 3, "loginId": 7, "Bearer": "x"}))
+        .fixture("UserLogoutApi", json!({"returnCode": 1}))
+        .fixture("UpsertUserAllApi", json!({"returnCode": 1, "apiName": "com.sega.maimai2servlet.api.UpsertUserAllApi"}))
+        .fixture("GetUserCardApi", json!({"userId": 1, "length": 1, "nextIndex": 0, "userCardList": [{"cardId": 6, "cardKind": 3, "cardTypeId": 6, "charaId": 0, "mapId": 0, "startDate": "2025-07-01 00:00:00", "endDate": "2038-01-01 00:00:00"}]}))
+        .start()
+        .await?;
+    let client = server.client_builder().build()?;
+
+    let login = UserLoginRequest {
+        user_id: 1,
+        access_code: String::new(),
+        region_id: 22,
+        place_id: 3490,
+        client_id: "A63E01E9564".to_string(),
+        date_time: 1751371200,
+        is_continue: false,
+        generic_flag: 0,
+    };
+    let response = client.user_login_api(&login, "1".to_string()).await?;
+    assert_eq!((response.return_code, response.login_count, response.login_id), (1, 42, 7));
+    assert_eq!(response.extra["Bearer"], "x");
+
+    let logout = UserLogoutRequest {
+        user_id: 1,
+        access_code: String::new(),
+        region_id: 22,
+        place_id: 3490,
+        client_id: "A63E01E9564".to_string(),
+        date_time: 1751371200,
+        logout_type: 1,
+    };
+    assert!(client.user_logout_api(&logout, "1".to_string()).await?.is_success());
+    assert_eq!(server.requests().last().unwrap().body["type"], 1);
+
+    assert!(client.upsert_user_all_api(&serde_json::json!({"userId": 1}), "1".to_string()).await?.is_success());
+
+    let cards = client.get_user_card_api(&UserPagedRequest { user_id: 1, next_index: 0, max_count: 20 }, "1".to_string()).await?;
+    assert_eq!(cards.user_card_list[0].card_type_id, 6);
     Ok(())
 }
+
+#[test]
+fn snake_to_pascal_names() {
+    assert_eq!(snake_to_pascal("get_user_preview_api"), "GetUserPreviewApi");
+    assert_eq!(snake_to_pascal("ping"), "Ping");
+}
@@ -0,0 +1,192 @@
+//! 日志初始化与脱敏
+//!
+//! [`init_logger`] 安装的 subscriber 会在输出前对每一行调用 [`redact`]，
+//! 所以任何 crate 用 `tracing` 打出的 `sessionId`、`_t` cookie、二维码和密钥都不会原样落盘
+//!
+//! - 日志级别：`RUST_LOG`，默认 `info`
+//! - 输出格式：`MAI_LOG_FORMAT=json` 输出 JSON，否则为普通文本
+
+use std::fmt;
+use std::io::{self, Write};
+use lazy_static::lazy_static;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+const MASK: &str = "***";
+
+lazy_static! {
+    /// JSON 字段：`"sessionId":123` / `"qrCode":"SGWC..."`
+    ///
+    /// `MAI_LOG_FORMAT=json` 时字段值里的 JSON 会再转义一次（`\"sessionId\":123`），引号前的 `\` 原样保留
+    static ref JSON_FIELD: Regex = Regex::new(
+        r#"(?i)(\\?)"(sessionId|session_id|qrCode|key|accessCode|aes_key|aes_iv|token|fish_token)\\?"(\s*:\s*)(?:\\"(?:[^"\\]|\\[^"])*\\"|"(?:[^"\\]|\\.)*"|-?\d+)"#
+    ).unwrap();
+    /// Debug 输出的结构体字段：`session_id: 123`
+    static ref DEBUG_FIELD: Regex = Regex::new(
+        r#"\b(session_id|open_user_id|qr_code|aes_key|aes_iv)(:\s*)(?:\\"(?:[^"\\]|\\[^"])*\\"|"(?:[^"\\]|\\.)*"|-?\d+)"#
+    ).unwrap();
+    /// cookie 与 query：`_t=xxx`、`?t=xxx`、`code=xxx`
+    static ref KEY_VALUE: Regex = Regex::new(
+        r#"\b(_t|t|code|sessionId|session_id|token)=([^;&\s"',\\]+)"#
+    ).unwrap();
+    /// 舞萌机台二维码：`SGWCMAID` + 时间戳 + 十六进制负载
+    static ref SGWC_QR: Regex = Regex::new(r"SGWC[A-Z]{4}[0-9A-F]{12,}").unwrap();
+}
+
+/// 去掉日志文本中的凭据
+pub fn redact(text: &str) -> String {
+    let text = JSON_FIELD.replace_all(text, format!(r#"${{1}}"${{2}}${{1}}"${{3}}${{1}}"{MASK}${{1}}""#));
+    let text = DEBUG_FIELD.replace_all(&text, format!("${{1}}${{2}}{MASK}"));
+    let text = KEY_VALUE.replace_all(&text, format!("$1={MASK}"));
+    SGWC_QR.replace_all(&text, format!("SGWC{MASK}")).into_owned()
+}
+
+/// UID 的短哈希，用于在日志中关联同一用户而不暴露 UID
+pub fn uid_hash(uid: &str) -> String {
+    let digest = Sha256::digest(uid.as_bytes());
+    digest[..4].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// 在 `Debug` 输出中代替密钥等字段
+pub(crate) struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// 写入前先脱敏的 writer
+pub struct RedactingWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.inner.write_all(redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct RedactingMakeWriter;
+
+impl<'a> MakeWriter<'a> for RedactingMakeWriter {
+    type Writer = RedactingWriter<io::Stdout>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter { inner: io::stdout() }
+    }
+}
+
+/// 安装全局 subscriber，重复调用不会报错
+pub fn init_logger() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(RedactingMakeWriter);
+    let result = if std::env::var("MAI_LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json")) {
+        builder.json().try_init()
+    } else {
+        builder.try_init()
+    };
+    // 已经初始化过时 try_init 返回错误，忽略即可
+    result.ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_json_fields() {
+        let text = r#"{"userId":1,"sessionId":1234567,"qrCode":"abc","key":"DEADBEEF"}"#;
+        assert_eq!(
+            redact(text),
+            r#"{"userId":1,"sessionId":"***","qrCode":"***","key":"***"}"#
+        );
+    }
+
+    #[test]
+    fn redacts_cookies_and_query() {
+        assert_eq!(redact("Cookie: _t=abcdef; userId=42"), "Cookie: _t=***; userId=42");
+        assert_eq!(
+            redact("https://maimai.wahlap.com/maimai-mobile/?t=abc123"),
+            "https://maimai.wahlap.com/maimai-mobile/?t=***"
+        );
+        assert_eq!(redact("callback?r=1&code=xyz&state=s"), "callback?r=1&code=***&state=s");
+    }
+
+    #[test]
+    fn redacts_debug_fields_and_qr_codes() {
+        assert_eq!(
+            redact("LoginResponse { user_id: 1, session_id: 998877 }"),
+            "LoginResponse { user_id: 1, session_id: *** }"
+        );
+        assert_eq!(
+            redact("qr SGWCMAID250702201530F50EFA944761EE done"),
+            "qr SGWC*** done"
+        );
+    }
+
+    #[test]
+    fn redacts_escaped_json() {
+        assert_eq!(
+            redact(r#"{"payload":"{\"userId\":1,\"sessionId\":998877,\"qrCode\":\"abc\"}","cookie":"_t=abc\"x"}"#),
+            r#"{"payload":"{\"userId\":1,\"sessionId\":\"***\",\"qrCode\":\"***\"}","cookie":"_t=***\"x"}"#
+        );
+        assert_eq!(
+            redact(r#"{"message":"LoginResponse { session_id: \"998877\" }"}"#),
+            r#"{"message":"LoginResponse { session_id: *** }"}"#
+        );
+    }
+
+    #[test]
+    fn json_format_output_is_redacted() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || RedactingWriter { inner: writer.clone() })
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!(payload = %r#"{"userId":1,"sessionId":998877}"#, cookie = "_t=abcdef", "request");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("998877") && !output.contains("abcdef"), "{output}");
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["fields"]["payload"], r#"{"userId":1,"sessionId":"***"}"#);
+        assert_eq!(line["fields"]["cookie"], "_t=***");
+    }
+
+    #[test]
+    fn uid_hash_is_stable() {
+        assert_eq!(uid_hash("12771153"), uid_hash("12771153"));
+        assert_ne!(uid_hash("12771153"), uid_hash("12771154"));
+        assert_eq!(uid_hash("1").len(), 8);
+    }
+}
@@ -0,0 +1,517 @@
+//! 本地 mock 标题服务器，用于离线测试
+//!
+//! 与真实服务器使用相同的协议：路径为 [`sdgb_api_hash`] 计算出的 API 哈希，
+//! 请求和响应都是 zlib + AES-CBC（[`AesPkcs7`]）。每个 API 名对应一份 fixture 或一个处理函数
+//!
+//! ```ignore
+//! let server = MockTitleServer::builder(cipher, "salt")
+//!     .fixture("GetUserPreviewApi", json!({"userId": 1, "userName": "TEST"}))
+//!     .start()
+//!     .await?;
+//! let client = server.client_builder().build()?;
+//! let preview = client.get_user_preview_api(&UserIdRequest::new(1), "1".to_string()).await?;
+//! ```
+//!
+//! fixture 为数组时视为分页数据：第一页对应 `nextIndex = 0`，之后每一页对应上一页返回的 `nextIndex`
+//!
+//! [`MockAimeDb`] 是 AimeDB 二维码接口的替身，校验请求签名和时间戳，按二维码负载返回预设的 `userID` / `errorID`
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::body::Bytes;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use crate::aes_pkcs7::AesPkcs7;
+use crate::aimedb::calc_sega_aimedb_auth_key;
+use crate::title_server::{sdgb_api_hash, RetryPolicy, TitleServerClientBuilder};
+use crate::utils::china_offset;
+
+/// 处理函数的返回值
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// 加密后以 200 返回
+    Json(Value),
+    /// 原样返回状态码和明文 body
+    Status(u16, String),
+    /// 以 200 返回未加密的原始字节，用于测试解密失败
+    Raw(Vec<u8>),
+}
+
+type Handler = Arc<dyn Fn(&Value) -> MockReply + Send + Sync>;
+
+#[derive(Clone)]
+enum Responder {
+    Fixture(Value),
+    Handler(Handler),
+}
+
+/// mock 收到的一次请求
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub api: String,
+    pub user_agent: String,
+    pub body: Value,
+}
+
+struct MockState {
+    cipher: AesPkcs7,
+    /// API 哈希 -> (API 名, 响应)
+    routes: HashMap<String, (String, Responder)>,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+pub struct MockTitleServerBuilder {
+    cipher: AesPkcs7,
+    hash_salt: String,
+    routes: HashMap<String, Responder>,
+}
+
+impl MockTitleServerBuilder {
+    /// 为 `api`（如 `GetUserPreviewApi`）返回固定 JSON
+    pub fn fixture(mut self, api: &str, value: Value) -> Self {
+        self.routes.insert(api.to_string(), Responder::Fixture(value));
+        self
+    }
+
+    /// 为 `api` 注册处理函数，参数为解密后的请求 JSON
+    pub fn handler<F>(mut self, api: &str, handler: F) -> Self
+    where
+        F: Fn(&Value) -> MockReply + Send + Sync + 'static,
+    {
+        self.routes.insert(api.to_string(), Responder::Handler(Arc::new(handler)));
+        self
+    }
+
+    /// 加载目录下所有 `<ApiName>.json` 作为 fixture
+    pub fn fixtures_dir(mut self, dir: impl AsRef<Path>) -> io::Result<Self> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let Some(api) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let value = serde_json::from_str(&fs::read_to_string(&path)?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+                self = self.fixture(api, value);
+            }
+        }
+        Ok(self)
+    }
+
+    /// 在随机端口上启动
+    pub async fn start(self) -> io::Result<MockTitleServer> {
+        self.start_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    pub async fn start_on(self, addr: SocketAddr) -> io::Result<MockTitleServer> {
+        let routes = self
+            .routes
+            .into_iter()
+            .map(|(api, responder)| (sdgb_api_hash(&api, &self.hash_salt), (api, responder)))
+            .collect();
+        let state = Arc::new(MockState {
+            cipher: self.cipher.clone(),
+            routes,
+            requests: Mutex::new(Vec::new()),
+        });
+        let app = Router::new()
+            .route("/Maimai2Servlet/:api_hash", post(handle_title_server))
+            .with_state(state.clone());
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                warn!(error = %e, "mock title server stopped");
+            }
+        });
+        Ok(MockTitleServer {
+            addr,
+            cipher: self.cipher,
+            hash_salt: self.hash_salt,
+            state,
+            task,
+        })
+    }
+}
+
+/// 运行中的 mock 标题服务器，drop 时停止
+pub struct MockTitleServer {
+    addr: SocketAddr,
+    cipher: AesPkcs7,
+    hash_salt: String,
+    state: Arc<MockState>,
+    task: JoinHandle<()>,
+}
+
+impl MockTitleServer {
+    pub fn builder(cipher: AesPkcs7, hash_salt: impl Into<String>) -> MockTitleServerBuilder {
+        MockTitleServerBuilder {
+            cipher,
+            hash_salt: hash_salt.into(),
+            routes: HashMap::new(),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 可以直接填入配置 `endpoint` / `MAI_ENDPOINT` 的地址
+    pub fn endpoint(&self) -> String {
+        format!("http://{}/Maimai2Servlet/", self.addr)
+    }
+
+    /// 指向本服务器的客户端，重试间隔为 0
+    pub fn client_builder(&self) -> TitleServerClientBuilder {
+        TitleServerClientBuilder::new(self.endpoint(), self.cipher.clone(), self.hash_salt.clone()).retry(RetryPolicy {
+            interval: Default::default(),
+            ..RetryPolicy::default()
+        })
+    }
+
+    /// 目前为止收到的所有请求
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockTitleServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle_title_server(
+    State(state): State<Arc<MockState>>,
+    UrlPath(api_hash): UrlPath<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some((api, responder)) = state.routes.get(&api_hash) else {
+        return (StatusCode::NOT_FOUND, "unknown api").into_response();
+    };
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if !user_agent.starts_with(&format!("{api_hash}#")) {
+        return (StatusCode::BAD_REQUEST, "user agent does not match api hash").into_response();
+    }
+    let request = match state.cipher.decrypt(&body).map(|b| serde_json::from_slice::<Value>(&b)) {
+        Ok(Ok(request)) => request,
+        _ => return (StatusCode::BAD_REQUEST, "cannot decode request body").into_response(),
+    };
+    debug!(%api, "mock title server request");
+    state.requests.lock().unwrap().push(RecordedRequest {
+        api: api.clone(),
+        user_agent,
+        body: request.clone(),
+    });
+
+    let reply = match responder {
+        Responder::Fixture(Value::Array(pages)) => MockReply::Json(select_page(pages, &request)),
+        Responder::Fixture(value) => MockReply::Json(value.clone()),
+        Responder::Handler(handler) => handler(&request),
+    };
+    match reply {
+        MockReply::Json(value) => match state.cipher.encrypt(value.to_string().as_bytes()) {
+            Ok(body) => (StatusCode::OK, body).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        },
+        MockReply::Status(status, body) => {
+            (StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR), body).into_response()
+        }
+        MockReply::Raw(body) => (StatusCode::OK, body).into_response(),
+    }
+}
+
+/// 按请求的 `nextIndex` 选出对应的分页
+fn select_page(pages: &[Value], request: &Value) -> Value {
+    let wanted = request["nextIndex"].as_i64().unwrap_or(0);
+    let mut index = 0;
+    for page in pages {
+        if index == wanted {
+            return page.clone();
+        }
+        index = page["nextIndex"].as_i64().unwrap_or(0);
+        if index == 0 {
+            break;
+        }
+    }
+    json!({ "userId": request["userId"], "length": 0, "nextIndex": 0, "userMusicList": [] })
+}
+
+struct AimeDbState {
+    chip_id: String,
+    common_key: String,
+    /// 二维码负载 -> userID
+    users: HashMap<String, i64>,
+    /// 二维码负载 -> errorID
+    errors: HashMap<String, i64>,
+    max_skew: Duration,
+    requests: Mutex<Vec<Value>>,
+}
+
+pub struct MockAimeDbBuilder {
+    state: AimeDbState,
+}
+
+impl MockAimeDbBuilder {
+    /// 二维码负载（`SGWCMAID` + 时间戳之后的 64 位十六进制）对应的用户
+    pub fn user(mut self, qr_payload: &str, user_id: i64) -> Self {
+        self.state.users.insert(qr_payload.to_string(), user_id);
+        self
+    }
+
+    /// 该二维码负载返回指定的 `errorID`
+    pub fn error(mut self, qr_payload: &str, error_id: i64) -> Self {
+        self.state.errors.insert(qr_payload.to_string(), error_id);
+        self
+    }
+
+    /// 允许的请求时间戳偏差，默认 5 分钟
+    pub fn max_skew(mut self, max_skew: Duration) -> Self {
+        self.state.max_skew = max_skew;
+        self
+    }
+
+    /// 在随机端口上启动
+    pub async fn start(self) -> io::Result<MockAimeDb> {
+        self.start_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    pub async fn start_on(self, addr: SocketAddr) -> io::Result<MockAimeDb> {
+        let state = Arc::new(self.state);
+        let app = Router::new()
+            .route("/wc_aime/api/get_data", post(handle_aimedb))
+            // 固定返回非 JSON，用于测试解码失败
+            .route("/wc_aime/api/garbage", post(|| async { "<html>not json</html>" }))
+            .with_state(state.clone());
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                warn!(error = %e, "mock aimedb stopped");
+            }
+        });
+        Ok(MockAimeDb { addr, state, task })
+    }
+}
+
+/// 运行中的 mock AimeDB，drop 时停止
+pub struct MockAimeDb {
+    addr: SocketAddr,
+    state: Arc<AimeDbState>,
+    task: JoinHandle<()>,
+}
+
+impl MockAimeDb {
+    /// 签名或时间戳校验失败、二维码未登记时返回的 `errorID`
+    ///
+    /// 真实 AimeDB 的错误码没有公开资料，这里是 mock 自己约定的值
+    pub const ERROR_REJECTED: i64 = -1;
+
+    pub fn builder(chip_id: impl Into<String>, common_key: impl Into<String>) -> MockAimeDbBuilder {
+        MockAimeDbBuilder {
+            state: AimeDbState {
+                chip_id: chip_id.into(),
+                common_key: common_key.into(),
+                users: HashMap::new(),
+                errors: HashMap::new(),
+                max_skew: Duration::from_secs(300),
+                requests: Mutex::new(Vec::new()),
+            },
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 可以直接填入配置 `aimedb_url` / `MAI_AIMEDB_URL` 的地址
+    pub fn url(&self) -> String {
+        format!("http://{}/wc_aime/api/get_data", self.addr)
+    }
+
+    /// 目前为止收到的所有请求体
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockAimeDb {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle_aimedb(State(state): State<Arc<AimeDbState>>, Json(request): Json<Value>) -> Json<Value> {
+    state.requests.lock().unwrap().push(request.clone());
+    let chip_id = request["chipID"].as_str().unwrap_or_default();
+    let timestamp = request["timestamp"].as_str().unwrap_or_default();
+    let key = request["key"].as_str().unwrap_or_default();
+    let qr_code = request["qrCode"].as_str().unwrap_or_default();
+
+    let error_id = if chip_id != state.chip_id
+        || key != calc_sega_aimedb_auth_key(chip_id, timestamp, &state.common_key)
+        || !timestamp_is_fresh(timestamp, state.max_skew)
+    {
+        MockAimeDb::ERROR_REJECTED
+    } else if let Some(&error_id) = state.errors.get(qr_code) {
+        error_id
+    } else if let Some(user_id) = state.users.get(qr_code) {
+        return Json(json!({ "errorID": 0, "key": key, "timestamp": timestamp, "userID": user_id }));
+    } else {
+        MockAimeDb::ERROR_REJECTED
+    };
+    debug!(error_id, "mock aimedb rejected request");
+    Json(json!({ "errorID": error_id, "key": key, "timestamp": timestamp }))
+}
+
+/// `YYMMDDhhmmss` 格式的北京时间与当前时间相差不超过 `max_skew`
+fn timestamp_is_fresh(timestamp: &str, max_skew: Duration) -> bool {
+    let Ok(time) = NaiveDateTime::parse_from_str(timestamp, "%y%m%d%H%M%S") else {
+        return false;
+    };
+    let Some(time) = china_offset().from_local_datetime(&time).single() else {
+        return false;
+    };
+    (Utc::now() - time.with_timezone(&Utc)).abs().to_std().is_ok_and(|skew| skew <= max_skew)
+}
+
+/// 测试用密钥
+#[cfg(test)]
+pub(crate) fn test_cipher() -> AesPkcs7 {
+    AesPkcs7::new("0123456789abcdef0123456789abcdef", "0123456789abcdef")
+}
+
+/// 测试用乐曲，`id` 不小于 10000 时为 DX 谱面
+#[cfg(test)]
+pub(crate) fn test_song(id: i32, ds: &[f32], is_new: bool) -> crate::jsons::music_data::Song {
+    crate::jsons::music_data::Song {
+        id: id.to_string(),
+        title: format!("song {id}"),
+        song_type: if id >= 10000 { "DX" } else { "SD" }.to_string(),
+        ds: ds.to_vec(),
+        level: ds.iter().map(|d| d.to_string()).collect(),
+        basic_info: crate::jsons::music_data::BasicInfo {
+            title: format!("song {id}"),
+            artist: String::new(),
+            genre: String::new(),
+            from: if is_new { "maimai でらっくす PRiSM PLUS" } else { "maimai" }.to_string(),
+            is_new,
+        },
+    }
+}
+
+/// 测试用成绩，游玩次数为 1
+#[cfg(test)]
+pub(crate) fn test_detail(music_id: i32, level: i32, achievement: i32) -> crate::UserMusicDetail {
+    crate::UserMusicDetail {
+        music_id,
+        level,
+        play_count: 1,
+        achievement,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::title_server::TitleServerError;
+    use crate::vo::UserIdRequest;
+
+    #[tokio::test]
+    async fn serves_fixture_and_records_request() {
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .fixture("GetUserPreviewApi", json!({ "userId": 7, "userName": "ＭＯＣＫ", "playerRating": 12345 }))
+            .start()
+            .await
+            .unwrap();
+        let client = server.client_builder().build().unwrap();
+        let preview = client
+            .get_user_preview_api(&UserIdRequest::new(7), "7".to_string())
+            .await
+            .unwrap();
+        assert_eq!(preview.user_name, "ＭＯＣＫ");
+        assert_eq!(preview.player_rating, 12345);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].api, "GetUserPreviewApi");
+        assert_eq!(requests[0].body, json!({ "userId": 7 }));
+        assert!(requests[0].user_agent.ends_with("#7"));
+    }
+
+    #[tokio::test]
+    async fn wrong_key_is_a_decrypt_error() {
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .handler("Ping", |_| MockReply::Raw(vec![0; 32]))
+            .start()
+            .await
+            .unwrap();
+        let client = server.client_builder().build().unwrap();
+        let err = client.ping(&json!({}), "A63E01E9564".to_string()).await.unwrap_err();
+        assert!(matches!(err, TitleServerError::Decrypt(_)), "{err:?}");
+        assert_eq!(server.requests().len(), 1, "decrypt errors must not be retried");
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .handler("Ping", |_| MockReply::Status(503, "busy".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let client = server.client_builder().build().unwrap();
+        let err = client.ping(&json!({}), "A63E01E9564".to_string()).await.unwrap_err();
+        assert!(matches!(err, TitleServerError::Http { .. }), "{err:?}");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn writes_are_not_retried() {
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .handler("UpsertUserAllApi", |_| MockReply::Status(503, "busy".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let client = server.client_builder().build().unwrap();
+        let err = client.upsert_user_all_api(&json!({ "userId": 7 }), "7".to_string()).await.unwrap_err();
+        assert!(matches!(err, TitleServerError::Http { .. }), "{err:?}");
+        assert_eq!(server.requests().len(), 1, "a timed-out write may already have been applied");
+    }
+
+    #[tokio::test]
+    async fn unknown_api_is_404() {
+        let server = MockTitleServer::builder(test_cipher(), "salt").start().await.unwrap();
+        let client = server.client_builder().build().unwrap();
+        let err = client.ping(&json!({}), "A63E01E9564".to_string()).await.unwrap_err();
+        assert!(matches!(err, TitleServerError::Http { status, .. } if status == 404), "{err:?}");
+    }
+
+    #[test]
+    fn aimedb_timestamp_freshness() {
+        let now = Utc::now().with_timezone(&china_offset());
+        let skew = Duration::from_secs(300);
+        assert!(timestamp_is_fresh(&now.format("%y%m%d%H%M%S").to_string(), skew));
+        let stale = now - chrono::Duration::minutes(10);
+        assert!(!timestamp_is_fresh(&stale.format("%y%m%d%H%M%S").to_string(), skew));
+        assert!(!timestamp_is_fresh("not a time", skew));
+    }
+}
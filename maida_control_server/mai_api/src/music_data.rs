@@ -8,26 +8,48 @@ use crate::jsons::music_data::{MusicData, Song};
 lazy_static! {
     pub static ref music_data: RwLock<MusicData> = RwLock::new(MusicData::new());
 }
-pub fn get_music_data(key:i32) -> Option<Song> {
-    let data = music_data.read().unwrap();
-    if data.is_empty() {
-        load_music_data();
+/// 第一次使用时加载乐曲数据
+///
+/// 必须在拿读锁之前调用，`load_music_data` 需要写锁
+fn ensure_loaded() {
+    if music_data.read().unwrap().is_empty()
+        && let Err(e) = load_music_data()
+    {
+        tracing::warn!(error = %e, "加载乐曲数据失败");
     }
-    data.get(&key).cloned()
+}
+
+pub fn get_music_data(key:i32) -> Option<Song> {
+    ensure_loaded();
+    music_data.read().unwrap().get(&key).cloned()
+}
+/// 全部乐曲数据
+pub fn get_all_music_data() -> Vec<Song> {
+    ensure_loaded();
+    music_data.read().unwrap().values().cloned().collect()
 }
 pub fn get_music_title(key: i32) -> Option<String> {
+    ensure_loaded();
+    music_data.read().unwrap().get(&key).map(|song| song.title.clone())
+}
+
+/// 按曲名查找乐曲 ID，同名时 `dx` 为 true 选 DX 谱（ID ≥ 10000），否则选标准谱
+pub fn find_music_id_by_title(title: &str, dx: bool) -> Option<i32> {
+    ensure_loaded();
     let data = music_data.read().unwrap();
-    if data.is_empty() {
-        load_music_data().ok()?;
-    }
-    if let Some(song) = data.get(&key) {
-        Some(song.title.clone())
-    }else{
-        None
-    }
+    let mut ids: Vec<i32> = data
+        .iter()
+        .filter(|(_, song)| song.title == title)
+        .map(|(id, _)| *id)
+        .collect();
+    ids.sort();
+    ids.iter()
+        .copied()
+        .find(|id| (*id >= 10000) == dx)
+        .or_else(|| ids.first().copied())
 }
 pub fn load_music_data() -> Result<()> {
-    let data = fs::read_to_string(crate::mai_api::config::MUSIC_DB_PATH)?;
+    let data = fs::read_to_string(&crate::config::get().music_db_path)?;
     let songs: Vec<Song> = serde_json::from_str(&data)?;
     let mut song_map:MusicData = HashMap::new();
     for song in songs {
@@ -0,0 +1,421 @@
+//! B50 计算
+//!
+//! DX rating 由两部分相加：旧版本曲目中单曲 rating 最高的 35 张谱面（B35），
+//! 当前版本曲目中最高的 15 张（B15）。是否为当前版本以乐曲数据的 `basic_info.is_new` 为准，
+//! 宴会场谱面不计入
+//!
+//! [`rating_targets`] 在当前 B50 的基础上，计算每张谱面让总 rating 提升所需的达成率
+
+use std::collections::HashMap;
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+use crate::jsons::music_data::Song;
+use crate::helper_get_user_music_detail::get_user_full_music_detail_with;
+use crate::music_data::{get_all_music_data, get_music_data};
+use crate::utils::{single_ra, Rank, MAX_ACHIEVEMENT, UTAGE_LEVEL};
+use super::{default_client, TitleServerClient, UserIdRequest, UserMusicDetail};
+
+pub const OLD_COUNT: usize = 35;
+pub const NEW_COUNT: usize = 15;
+
+/// 宴会场乐曲的 ID 从这里开始
+const UTAGE_MIN_ID: i32 = 100000;
+
+/// 宴会场谱面不计入 rating
+fn is_utage(music_id: i32, level: i32) -> bool {
+    music_id >= UTAGE_MIN_ID || level == UTAGE_LEVEL
+}
+
+/// 计入 rating 的单张谱面
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatedChart {
+    pub music_id: i32,
+    pub level: i32,
+    pub title: String,
+    /// 达成率 ×10000
+    pub achievement: i32,
+    /// 定数
+    pub constant: f32,
+    pub ra: i32,
+    pub is_new: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Best50 {
+    pub rating: i32,
+    pub old_rating: i32,
+    pub new_rating: i32,
+    /// 旧版本 B35，按 rating 从高到低
+    pub old: Vec<RatedChart>,
+    /// 当前版本 B15，按 rating 从高到低
+    pub new: Vec<RatedChart>,
+}
+
+impl Best50 {
+    /// 谱面单曲 rating 变为 `ra` 后总 rating 的提升
+    ///
+    /// 谱面已在 B35 / B15 中时替换自身，否则替换对应部分中最低的一张（未满时直接加入）
+    pub fn gain_for(&self, music_id: i32, level: i32, is_new: bool, ra: i32) -> i32 {
+        let (pool, count) = if is_new { (&self.new, NEW_COUNT) } else { (&self.old, OLD_COUNT) };
+        if let Some(chart) = pool.iter().find(|c| c.music_id == music_id && c.level == level) {
+            return (ra - chart.ra).max(0);
+        }
+        if pool.len() < count {
+            return ra;
+        }
+        (ra - pool.last().map_or(0, |c| c.ra)).max(0)
+    }
+}
+
+/// 计算结果和游戏内显示的 rating
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingCheck {
+    pub best50: Best50,
+    /// `GetUserPreviewApi` 返回的 `playerRating`
+    pub player_rating: i32,
+}
+
+impl RatingCheck {
+    /// 不一致通常是本地乐曲数据（定数或版本）过期
+    pub fn matches(&self) -> bool {
+        self.best50.rating == self.player_rating
+    }
+}
+
+/// 单张谱面的 rating，宴会场或乐曲数据中没有该难度时返回 `None`
+pub fn rate_chart(detail: &UserMusicDetail, song: &Song) -> Option<RatedChart> {
+    if is_utage(detail.music_id, detail.level) {
+        return None;
+    }
+    let constant = *song.ds.get(usize::try_from(detail.level).ok()?)?;
+    Some(RatedChart {
+        music_id: detail.music_id,
+        level: detail.level,
+        title: song.title.clone(),
+        achievement: detail.achievement,
+        constant,
+        ra: single_ra(detail.achievement, constant),
+        is_new: song.basic_info.is_new,
+    })
+}
+
+pub fn best50(details: &[UserMusicDetail]) -> Best50 {
+    best50_with(details, get_music_data)
+}
+
+/// `lookup` 按乐曲 ID 查乐曲数据，查不到的谱面跳过
+pub fn best50_with(details: &[UserMusicDetail], lookup: impl Fn(i32) -> Option<Song>) -> Best50 {
+    let (mut new, mut old): (Vec<_>, Vec<_>) = details
+        .iter()
+        .filter_map(|detail| match lookup(detail.music_id) {
+            Some(song) => rate_chart(detail, &song),
+            None => {
+                warn!(music_id = detail.music_id, "unknown song, skipped in rating");
+                None
+            }
+        })
+        .partition(|chart| chart.is_new);
+    for (pool, count) in [(&mut old, OLD_COUNT), (&mut new, NEW_COUNT)] {
+        // rating 相同时达成率高的在前，结果与游戏内顺序一致且稳定
+        pool.sort_by(|a, b| {
+            b.ra.cmp(&a.ra)
+                .then(b.achievement.cmp(&a.achievement))
+                .then(a.music_id.cmp(&b.music_id))
+                .then(a.level.cmp(&b.level))
+        });
+        pool.truncate(count);
+    }
+    let old_rating = old.iter().map(|c| c.ra).sum();
+    let new_rating = new.iter().map(|c| c.ra).sum();
+    Best50 {
+        rating: old_rating + new_rating,
+        old_rating,
+        new_rating,
+        old,
+        new,
+    }
+}
+
+pub async fn get_user_best50(user_id: i32) -> Result<RatingCheck> {
+    get_user_best50_with(&*default_client()?, user_id, get_music_data).await
+}
+
+/// 拉取全部成绩计算 B50，并与 `GetUserPreviewApi` 的 `playerRating` 对比
+pub async fn get_user_best50_with(
+    client: &TitleServerClient,
+    user_id: i32,
+    lookup: impl Fn(i32) -> Option<Song>,
+) -> Result<RatingCheck> {
+    let details = get_user_full_music_detail_with(client, user_id).await?;
+    let preview = client
+        .get_user_preview_api(&UserIdRequest::new(user_id as i64), user_id.to_string())
+        .await?;
+    let check = RatingCheck {
+        best50: best50_with(&details, lookup),
+        player_rating: preview.player_rating,
+    };
+    if !check.matches() {
+        warn!(
+            calculated = check.best50.rating,
+            player_rating = check.player_rating,
+            "rating mismatch, music data may be outdated"
+        );
+    }
+    Ok(check)
+}
+
+/// 达到某个评级时的 rating
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankGain {
+    pub rank: Rank,
+    /// 该评级的最低达成率
+    pub achievement: i32,
+    pub ra: i32,
+    /// 总 rating 的提升
+    pub gain: i32,
+}
+
+/// 单张谱面的推分目标
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingTarget {
+    pub music_id: i32,
+    pub level: i32,
+    pub title: String,
+    pub constant: f32,
+    pub is_new: bool,
+    /// 当前达成率，没玩过为 0
+    pub achievement: i32,
+    /// 当前单曲 rating
+    pub ra: i32,
+    /// 总 rating 至少提升目标值所需的最低达成率
+    pub required_achievement: i32,
+    /// 达到各个更高评级时的提升，只列出能提升总 rating 的评级
+    pub rank_gains: Vec<RankGain>,
+}
+
+pub fn rating_targets(details: &[UserMusicDetail], gain: i32) -> Vec<RatingTarget> {
+    rating_targets_with(details, &get_all_music_data(), gain)
+}
+
+/// 列出所有能让总 rating 提升至少 `gain` 的谱面，所需达成率低的在前
+///
+/// `songs` 为候选乐曲，也用于计算当前 B50
+pub fn rating_targets_with(details: &[UserMusicDetail], songs: &[Song], gain: i32) -> Vec<RatingTarget> {
+    let gain = gain.max(1);
+    let songs: HashMap<i32, &Song> = songs
+        .iter()
+        .filter_map(|song| Some((song.id.parse().ok()?, song)))
+        .filter(|&(id, _)| id < UTAGE_MIN_ID)
+        .collect();
+    let best = best50_with(details, |id| songs.get(&id).map(|song| (*song).clone()));
+    let played: HashMap<(i32, i32), i32> = details
+        .iter()
+        .map(|d| ((d.music_id, d.level), d.achievement))
+        .collect();
+
+    let mut targets = Vec::new();
+    for (&music_id, song) in &songs {
+        for (level, &constant) in song.ds.iter().enumerate() {
+            let level = level as i32;
+            if is_utage(music_id, level) {
+                continue;
+            }
+            let achievement = played.get(&(music_id, level)).copied().unwrap_or_default();
+            let is_new = song.basic_info.is_new;
+            let gain_at = |a: i32| best.gain_for(music_id, level, is_new, single_ra(a, constant));
+            let Some(required_achievement) = min_achievement_for(gain_at, achievement, gain) else {
+                continue;
+            };
+            let rank_gains = Rank::ALL
+                .into_iter()
+                .filter(|rank| rank.min_achievement() > achievement)
+                .map(|rank| RankGain {
+                    rank,
+                    achievement: rank.min_achievement(),
+                    ra: single_ra(rank.min_achievement(), constant),
+                    gain: gain_at(rank.min_achievement()),
+                })
+                .filter(|r| r.gain > 0)
+                .collect();
+            targets.push(RatingTarget {
+                music_id,
+                level,
+                title: song.title.clone(),
+                constant,
+                is_new,
+                achievement,
+                ra: single_ra(achievement, constant),
+                required_achievement,
+                rank_gains,
+            });
+        }
+    }
+    targets.sort_by(|a, b| {
+        a.required_achievement
+            .cmp(&b.required_achievement)
+            .then(a.music_id.cmp(&b.music_id))
+            .then(a.level.cmp(&b.level))
+    });
+    targets
+}
+
+/// 二分查找 `gain_at` 不低于 `gain` 的最低达成率，`gain_at` 随达成率单调不减
+fn min_achievement_for(gain_at: impl Fn(i32) -> i32, current: i32, gain: i32) -> Option<i32> {
+    if current >= MAX_ACHIEVEMENT || gain_at(MAX_ACHIEVEMENT) < gain {
+        return None;
+    }
+    // gain_at(lo) < gain，gain_at(hi) >= gain
+    let (mut lo, mut hi) = (current, MAX_ACHIEVEMENT);
+    if gain_at(lo) >= gain {
+        return Some(lo);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if gain_at(mid) >= gain {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Some(hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde_json::json;
+    use crate::mock::{test_cipher, test_detail as detail, test_song as song, MockTitleServer};
+    use super::*;
+
+    #[test]
+    fn picks_top_35_old_and_15_new() {
+        let mut songs = HashMap::new();
+        let mut details = Vec::new();
+        // 旧曲 40 首、新曲 20 首，定数依次递增
+        for i in 0..40 {
+            songs.insert(100 + i, song(100 + i, &[5.0, 7.0, 10.0, 12.0 + i as f32 * 0.1], false));
+            details.push(detail(100 + i, 3, 1005000));
+        }
+        for i in 0..20 {
+            songs.insert(11000 + i, song(11000 + i, &[5.0, 7.0, 10.0, 12.0 + i as f32 * 0.1], true));
+            details.push(detail(11000 + i, 3, 1005000));
+        }
+        // 宴会场和未知乐曲不计入
+        details.push(detail(100, 10, 1010000));
+        details.push(detail(99999, 3, 1010000));
+
+        let best = best50_with(&details, |id| songs.get(&id).cloned());
+        assert_eq!((best.old.len(), best.new.len()), (OLD_COUNT, NEW_COUNT));
+        assert_eq!(best.old[0].music_id, 139);
+        assert_eq!(best.old[34].music_id, 105);
+        assert_eq!(best.new[14].music_id, 11005);
+        assert!(best.old.iter().all(|c| !c.is_new) && best.new.iter().all(|c| c.is_new));
+        assert!(best.old.windows(2).all(|w| w[0].ra >= w[1].ra));
+        assert_eq!(best.old_rating, best.old.iter().map(|c| c.ra).sum::<i32>());
+        assert_eq!(best.rating, best.old_rating + best.new_rating);
+    }
+
+    #[test]
+    fn missing_difficulty_is_skipped() {
+        // 没有 Re:Master 的乐曲只有 4 个定数
+        let s = song(834, &[4.0, 7.0, 10.5, 13.0], false);
+        assert!(rate_chart(&detail(834, 4, 1005000), &s).is_none());
+        let chart = rate_chart(&detail(834, 3, 1005000), &s).unwrap();
+        assert_eq!((chart.constant, chart.ra), (13.0, single_ra(1005000, 13.0)));
+    }
+
+    #[tokio::test]
+    async fn validated_against_player_rating() -> Result<()> {
+        let songs: HashMap<i32, Song> = [
+            (11663, song(11663, &[6.0, 8.0, 11.5, 13.6, 14.4], true)),
+            (834, song(834, &[4.0, 7.0, 10.5, 13.0], false)),
+            (11311, song(11311, &[5.0, 7.5, 12.2, 13.9], false)),
+        ]
+        .into();
+        let lookup = |id| songs.get(&id).cloned();
+        let expected = single_ra(1005000, 13.6) + single_ra(1003272, 14.4) + single_ra(989876, 13.0) + single_ra(971234, 12.2);
+
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .fixture("GetUserMusicApi", serde_json::from_str(include_str!("../tests/fixtures/title_server/GetUserMusicApi.json"))?)
+            .fixture("GetUserPreviewApi", json!({ "userId": 12771153, "playerRating": expected }))
+            .start()
+            .await?;
+        let check = get_user_best50_with(&server.client_builder().build()?, 12771153, lookup).await?;
+        assert!(check.matches());
+        assert_eq!((check.best50.old.len(), check.best50.new.len()), (2, 2));
+        assert_eq!(check.best50.new[0].ra, single_ra(1003272, 14.4));
+
+        // 游戏内 rating 与计算结果不一致时能被发现
+        let server = MockTitleServer::builder(test_cipher(), "salt")
+            .fixture("GetUserMusicApi", serde_json::from_str(include_str!("../tests/fixtures/title_server/GetUserMusicApi.json"))?)
+            .fixture("GetUserPreviewApi", serde_json::from_str(include_str!("../tests/fixtures/title_server/GetUserPreviewApi.json"))?)
+            .start()
+            .await?;
+        let check = get_user_best50_with(&server.client_builder().build()?, 12771153, lookup).await?;
+        assert_eq!(check.player_rating, 15234);
+        assert!(!check.matches());
+        Ok(())
+    }
+
+    #[test]
+    fn targets_need_minimal_achievement() {
+        let mut songs = Vec::new();
+        let mut details = Vec::new();
+        // B35、B15 全部是 13.0 的 SSS（单曲 280）
+        for i in 0..35 {
+            songs.push(song(100 + i, &[5.0, 7.0, 10.0, 13.0], false));
+            details.push(detail(100 + i, 3, 1000000));
+        }
+        for i in 0..15 {
+            songs.push(song(11000 + i, &[5.0, 7.0, 10.0, 13.0], true));
+            details.push(detail(11000 + i, 3, 1000000));
+        }
+        songs.push(song(200, &[5.0, 7.0, 10.0, 14.0], false));
+
+        let targets = rating_targets_with(&details, &songs, 1);
+        assert_eq!(targets.len(), 51);
+        let first = &targets[0];
+        assert_eq!((first.music_id, first.level, first.achievement, first.ra), (200, 3, 0, 0));
+        // 14.0 在 S+ 段需要单曲 281 才能挤掉 280
+        assert_eq!(first.required_achievement, 988741);
+        assert_eq!(single_ra(988741, 14.0), 281);
+        assert_eq!(single_ra(988740, 14.0), 280);
+        let gains: Vec<_> = first.rank_gains.iter().map(|r| (r.rank, r.ra, r.gain)).collect();
+        assert_eq!(
+            gains,
+            vec![(Rank::SS, 288, 8), (Rank::SSPlus, 293, 13), (Rank::SSS, 302, 22), (Rank::SSSPlus, 315, 35)]
+        );
+        // 已在 B50 中的谱面只和自己比
+        assert!(targets[1..].iter().all(|t| t.achievement == 1000000 && t.required_achievement == 1000713));
+        assert_eq!(single_ra(1000712, 13.0), 280);
+
+        assert!(rating_targets_with(&details, &songs, 36).is_empty());
+        assert_eq!(rating_targets_with(&details, &songs, 35).len(), 1);
+    }
+
+    #[test]
+    fn utage_songs_are_not_targets() {
+        let songs = [song(100, &[5.0, 7.0, 10.0, 13.0], false), song(100001, &[14.5], false)];
+        let details = [detail(100, 3, 1000000), detail(100001, 0, 1000000)];
+        let targets = rating_targets_with(&details, &songs, 1);
+        assert!(targets.iter().all(|t| t.music_id == 100), "{targets:?}");
+        assert_eq!(targets.len(), 4);
+    }
+
+    #[test]
+    fn gain_for_fills_then_replaces() {
+        let songs = [song(100, &[5.0, 7.0, 10.0, 13.0], false), song(101, &[5.0, 7.0, 10.0, 12.0], false)];
+        let best = best50_with(&[detail(100, 3, 1000000)], |id| songs.iter().find(|s| s.id == id.to_string()).cloned());
+        // B35 未满时直接加入
+        assert_eq!(best.gain_for(101, 3, false, 200), 200);
+        assert_eq!(best.gain_for(100, 3, false, 290), 10);
+        assert_eq!(best.gain_for(100, 3, false, 100), 0);
+        assert_eq!(min_achievement_for(|_| 0, MAX_ACHIEVEMENT, 1), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! 成绩历史
+//!
+//! `achievements` 表只保存每张谱面当前的最好成绩，同步时原地覆盖。`score_history` 表只追加：
+//! 每次同步时，与该谱面上一条历史不同的成绩会新增一行，据此查询 rating 变化、首次通关和刷新最好成绩。
+//!
+//! 按天统计时以北京时间（UTC+8）划分日期。第一次同步时所有成绩都会记为当天的新成绩
+
+use std::collections::HashMap;
+use anyhow::Result;
+use chrono::{NaiveDate, Utc};
+use sea_orm::prelude::*;
+use sea_orm::sea_query::{Expr, Query};
+use sea_orm::{QueryOrder, QuerySelect, Set};
+use serde_derive::{Deserialize, Serialize};
+use crate::config::{COMBO_ID_TO_NAME, SYNC_ID_TO_NAME};
+use crate::database::score_history::{ActiveModel, Column, Entity, Model};
+use crate::jsons::music_data::Song;
+use crate::music_data::get_music_data;
+use crate::rating::best50_with;
+use crate::utils::{china_offset, level_index, Rank};
+use super::UserMusicDetail;
+
+/// 达成率达到 A（80%）视为通关
+pub const CLEAR_ACHIEVEMENT: i32 = 800000;
+
+/// 北京时间的日期
+fn day_of(t: DateTimeUtc) -> NaiveDate {
+    t.with_timezone(&china_offset()).date_naive()
+}
+
+/// 北京时间 `date` 当天 0 点
+fn day_start(date: NaiveDate) -> DateTimeUtc {
+    date.and_time(Default::default())
+        .and_local_timezone(china_offset())
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// 记录一次同步的成绩，只写入有变化的谱面，返回新增的行数
+///
+/// `db` 可以是事务，与 `achievements` 的更新一起提交
+pub async fn record_scores(
+    db: &impl ConnectionTrait,
+    uid: i32,
+    songs: &[UserMusicDetail],
+    observed_at: DateTimeUtc,
+) -> Result<usize> {
+    // 每张谱面最后追加的一行
+    let latest_ids = Query::select()
+        .expr(Expr::col(Column::Id).max())
+        .from(Entity)
+        .and_where(Column::Uid.eq(uid))
+        .group_by_columns([Column::SongId, Column::LevelIndex])
+        .to_owned();
+    let latest: HashMap<(i32, i32), Model> = Entity::find()
+        .filter(Column::Id.in_subquery(latest_ids))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|row| ((row.song_id, row.level_index), row))
+        .collect();
+    let rows: Vec<ActiveModel> = songs
+        .iter()
+        .filter(|song| song.play_count > 0)
+        .filter_map(|song| {
+            let fc = COMBO_ID_TO_NAME.get(song.combo_status as usize).copied().unwrap_or_default().to_string();
+            let fs = SYNC_ID_TO_NAME.get(song.sync_status as usize).copied().unwrap_or_default().to_string();
+            let level = level_index(song.level);
+            let unchanged = latest.get(&(song.music_id, level)).is_some_and(|last| {
+                (last.achievements, last.dx_score, &last.fc, &last.fs) == (song.achievement, song.deluxscore_max, &fc, &fs)
+            });
+            if unchanged {
+                return None;
+            }
+            Some(ActiveModel {
+                id: sea_orm::NotSet,
+                uid: Set(uid),
+                song_id: Set(song.music_id),
+                level_index: Set(level),
+                achievements: Set(song.achievement),
+                dx_score: Set(song.deluxscore_max),
+                fc: Set(fc),
+                fs: Set(fs),
+                observed_at: Set(observed_at),
+            })
+        })
+        .collect();
+    let count = rows.len();
+    if count > 0 {
+        Entity::insert_many(rows).exec(db).await?;
+    }
+    Ok(count)
+}
+
+/// `uid` 在 `from`..=`to` 期间的历史，按时间先后排列
+async fn history_between(db: &DatabaseConnection, uid: i32, from: NaiveDate, to: NaiveDate) -> Result<Vec<Model>> {
+    let mut query = Entity::find()
+        .filter(Column::Uid.eq(uid))
+        .filter(Column::ObservedAt.gte(day_start(from)));
+    if let Some(next) = to.succ_opt() {
+        query = query.filter(Column::ObservedAt.lt(day_start(next)));
+    }
+    Ok(query
+        .order_by_asc(Column::ObservedAt)
+        .order_by_asc(Column::Id)
+        .all(db)
+        .await?)
+}
+
+/// `from` 之前每张谱面的最高达成率
+async fn best_before(db: &DatabaseConnection, uid: i32, from: NaiveDate) -> Result<HashMap<(i32, i32), i32>> {
+    let rows: Vec<(i32, i32, i32)> = Entity::find()
+        .select_only()
+        .column(Column::SongId)
+        .column(Column::LevelIndex)
+        .column_as(Expr::col(Column::Achievements).max(), "best")
+        .filter(Column::Uid.eq(uid))
+        .filter(Column::ObservedAt.lt(day_start(from)))
+        .group_by(Column::SongId)
+        .group_by(Column::LevelIndex)
+        .into_tuple()
+        .all(db)
+        .await?;
+    Ok(rows.into_iter().map(|(song_id, level_index, best)| ((song_id, level_index), best)).collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingPoint {
+    pub date: NaiveDate,
+    /// 当天结束时的 B50 rating
+    pub rating: i32,
+}
+
+/// 成绩变化，`previous` 为此前的最高达成率，之前没有记录时为 `None`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreChange {
+    pub song_id: i32,
+    pub level_index: i32,
+    pub previous: Option<i32>,
+    pub achievements: i32,
+    pub dx_score: i32,
+    pub rank: Rank,
+    pub observed_at: DateTimeUtc,
+}
+
+/// `from`..=`to` 每天结束时的 rating
+pub async fn rating_history(db: &DatabaseConnection, uid: i32, from: NaiveDate, to: NaiveDate) -> Result<Vec<RatingPoint>> {
+    rating_history_with(db, uid, from, to, get_music_data).await
+}
+
+pub async fn rating_history_with(
+    db: &DatabaseConnection,
+    uid: i32,
+    from: NaiveDate,
+    to: NaiveDate,
+    lookup: impl Fn(i32) -> Option<Song>,
+) -> Result<Vec<RatingPoint>> {
+    let mut best = best_before(db, uid, from).await?;
+    let rows = history_between(db, uid, from, to).await?;
+    let mut rows = rows.iter().peekable();
+    let mut points = Vec::new();
+    for date in from.iter_days().take_while(|d| *d <= to) {
+        while let Some(row) = rows.next_if(|row| day_of(row.observed_at) <= date) {
+            let achievement = best.entry((row.song_id, row.level_index)).or_default();
+            *achievement = (*achievement).max(row.achievements);
+        }
+        let details: Vec<UserMusicDetail> = best
+            .iter()
+            .map(|(&(music_id, level), &achievement)| UserMusicDetail {
+                music_id,
+                level,
+                play_count: 1,
+                achievement,
+                ..Default::default()
+            })
+            .collect();
+        points.push(RatingPoint {
+            date,
+            rating: best50_with(&details, &lookup).rating,
+        });
+    }
+    Ok(points)
+}
+
+/// 按时间先后遍历 `from`..=`to` 的历史，保留 `keep(此前最高达成率, 历史)` 为真的记录
+async fn changes_in(
+    db: &DatabaseConnection,
+    uid: i32,
+    from: NaiveDate,
+    to: NaiveDate,
+    keep: impl Fn(Option<i32>, &Model) -> bool,
+) -> Result<Vec<ScoreChange>> {
+    let mut best = best_before(db, uid, from).await?;
+    let mut changes = Vec::new();
+    for row in history_between(db, uid, from, to).await? {
+        let previous = best.get(&(row.song_id, row.level_index)).copied();
+        if keep(previous, &row) {
+            changes.push(ScoreChange {
+                song_id: row.song_id,
+                level_index: row.level_index,
+                previous,
+                achievements: row.achievements,
+                dx_score: row.dx_score,
+                rank: Rank::from_achievement(row.achievements),
+                observed_at: row.observed_at,
+            });
+        }
+        let achievement = best.entry((row.song_id, row.level_index)).or_insert(row.achievements);
+        *achievement = (*achievement).max(row.achievements);
+    }
+    Ok(changes)
+}
+
+/// `from`..=`to` 期间第一次通关（达成率 ≥ 80%）的谱面
+pub async fn first_clears(db: &DatabaseConnection, uid: i32, from: NaiveDate, to: NaiveDate) -> Result<Vec<ScoreChange>> {
+    changes_in(db, uid, from, to, |previous, row| {
+        row.achievements >= CLEAR_ACHIEVEMENT && previous.is_none_or(|p| p < CLEAR_ACHIEVEMENT)
+    })
+    .await
+}
+
+/// `from`..=`to` 期间刷新最好成绩（达成率提高）的记录，包括第一次游玩
+pub async fn personal_bests(db: &DatabaseConnection, uid: i32, from: NaiveDate, to: NaiveDate) -> Result<Vec<ScoreChange>> {
+    changes_in(db, uid, from, to, |previous, row| previous.is_none_or(|p| row.achievements > p)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use crate::database::{connect, user};
+    use crate::mock::{test_detail as detail, test_song as song};
+    use crate::utils::single_ra;
+    use super::*;
+
+    /// 北京时间 `day` 日中午
+    fn noon(day: u32) -> DateTimeUtc {
+        Utc.with_ymd_and_hms(2025, 7, day, 4, 0, 0).unwrap()
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 7, day).unwrap()
+    }
+
+    async fn db() -> DatabaseConnection {
+        let db = connect("sqlite::memory:").await.unwrap();
+        for qq in [10001, 10002] {
+            user::ActiveModel { qq: Set(qq), ..Default::default() }.insert(&db).await.unwrap();
+        }
+        db
+    }
+
+    #[tokio::test]
+    async fn only_changed_scores_are_appended() -> Result<()> {
+        let db = db().await;
+        assert_eq!(record_scores(&db, 1, &[detail(100, 3, 970000), detail(101, 3, 700000)], noon(1)).await?, 2);
+        assert_eq!(record_scores(&db, 1, &[detail(100, 3, 970000), detail(101, 3, 700000)], noon(2)).await?, 0);
+        assert_eq!(record_scores(&db, 1, &[detail(100, 3, 990000), detail(101, 3, 700000)], noon(3)).await?, 1);
+        // 其他用户互不影响
+        assert_eq!(record_scores(&db, 2, &[detail(100, 3, 990000)], noon(3)).await?, 1);
+        assert_eq!(Entity::find().count(&db).await?, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn utage_level_is_stored_as_zero() -> Result<()> {
+        let db = db().await;
+        assert_eq!(record_scores(&db, 1, &[detail(110001, 10, 1000000)], noon(1)).await?, 1);
+        assert_eq!(record_scores(&db, 1, &[detail(110001, 10, 1000000)], noon(2)).await?, 0);
+        let rows = Entity::find().all(&db).await?;
+        assert_eq!(rows.iter().map(|r| (r.song_id, r.level_index)).collect::<Vec<_>>(), vec![(110001, 0)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clears_bests_and_rating_over_time() -> Result<()> {
+        let db = db().await;
+        record_scores(&db, 1, &[detail(100, 3, 700000), detail(101, 3, 1000000)], noon(1)).await?;
+        record_scores(&db, 1, &[detail(100, 3, 850000), detail(101, 3, 1000000)], noon(3)).await?;
+        record_scores(&db, 1, &[detail(100, 3, 990000), detail(101, 3, 1000000), detail(102, 2, 800000)], noon(5)).await?;
+
+        let clears = first_clears(&db, 1, date(2), date(5)).await?;
+        let clears: Vec<_> = clears.iter().map(|c| (c.song_id, c.previous, c.achievements)).collect();
+        assert_eq!(clears, vec![(100, Some(700000), 850000), (102, None, 800000)]);
+
+        let bests = personal_bests(&db, 1, date(3), date(4)).await?;
+        assert_eq!(bests.len(), 1);
+        assert_eq!((bests[0].previous, bests[0].achievements, bests[0].rank), (Some(700000), 850000, Rank::A));
+        assert_eq!(personal_bests(&db, 1, date(1), date(5)).await?.len(), 5);
+
+        let songs = [
+            song(100, &[5.0, 7.0, 10.0, 13.0], false),
+            song(101, &[5.0, 7.0, 10.0, 12.0], false),
+            song(102, &[5.0, 7.0, 10.0], false),
+        ];
+        let lookup = |id: i32| songs.iter().find(|s| s.id == id.to_string()).cloned();
+        let points = rating_history_with(&db, 1, date(1), date(5), lookup).await?;
+        let ratings: Vec<_> = points.iter().map(|p| p.rating).collect();
+        let day1 = single_ra(700000, 13.0) + single_ra(1000000, 12.0);
+        let day3 = single_ra(850000, 13.0) + single_ra(1000000, 12.0);
+        let day5 = single_ra(990000, 13.0) + single_ra(1000000, 12.0) + single_ra(800000, 10.0);
+        assert_eq!(ratings, vec![day1, day1, day3, day3, day5]);
+        assert_eq!(points[4].date, date(5));
+        // 区间之前的成绩作为起点
+        let points = rating_history_with(&db, 1, date(4), date(4), lookup).await?;
+        assert_eq!(points.iter().map(|p| p.rating).collect::<Vec<_>>(), vec![day3]);
+        Ok(())
+    }
+}
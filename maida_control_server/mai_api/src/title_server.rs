@@ -1,165 +1,441 @@
-
-use aes;
-use block_modes;
-use flate2;
-
-use reqwest;
-use log;
-use env_logger;
-
-use aes::{Aes128, BlockCipher};
-use block_modes::{BlockMode, Cbc};
-use block_padding::Padding;
-use block_modes::block_padding::Pkcs7;
-use flate2::read::ZlibDecoder;
-use std::io::prelude::*;
-use std::io;
-use std::time::Duration;
-use reqwest::{Client, StatusCode};
-use reqwest::header::{HeaderMap, USER_AGENT, CONTENT_TYPE};
-use log::{info, debug, warn};
-use anyhow::Result;
-use env_logger::Env;
-use kovi::tokio::time::sleep;
-use md5;
+use std::time::{Duration, Instant};
+use reqwest::{Client, Proxy, StatusCode};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT, CONTENT_TYPE};
+use std::fmt;
+use std::string::FromUtf8Error;
+use std::sync::{Arc, RwLock};
+use lazy_static::lazy_static;
+use tokio::time::sleep;
+use tracing::{debug, info, instrument, warn, Instrument};
 use serde_json::Value;
-use super::config::*;
-use crate::mai_api::aes_pkcs7::{zlib_compress, AesPkcs7};
-type Aes128Cbc = Cbc<Aes128, Pkcs7>;
-
-// 舞萌DX 2024
-// omg it's leaking
-// AES_KEY = "n7bx6:@Fg_:2;5E89Phy7AyIcpxEQ:R@"
-// AES_IV = ";;KjR1C3hgB1ovXa"
-// OBFUSCATE_PARAM = "BEs2D5vW"
-
-// 2025
-
-
-pub fn init_logger() {
-    // let env = Env::default();
-    // 
-    // env_logger::init_from_env(env);
-}
-//
-// pub fn aes_encrypt(data: &[u8]) -> Vec<u8> {
-//     let cipher = Aes128Cbc::new_from_slices(AES_KEY, AES_IV).unwrap();
-//     cipher.encrypt_vec(data)
-// }
-//
-// pub fn aes_decrypt(data: &[u8]) -> Vec<u8> {
-//     let cipher = Aes128Cbc::new_from_slices(AES_KEY, AES_IV).unwrap();
-//     cipher.decrypt_vec(data).unwrap()
-// }
-//
-// pub fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
-//     let mut decoder = ZlibDecoder::new(data);
-//     let mut decompressed = Vec::new();
-//     decoder.read_to_end(&mut decompressed)?;
-//     Ok(decompressed)
-// }
-fn get_sdgb_api_hash(api: &str) -> String {
-    let input = format!("{}MaimaiChn{}", api,OBFUSCATE_PARAM);
+use super::config::{self, Config};
+use crate::aes_pkcs7::{zlib_uncompress, AesPkcs7};
+use crate::logging::{redact, uid_hash, Redacted};
+
+pub fn get_sdgb_api_hash(api: &str) -> String {
+    sdgb_api_hash(api, &config::get().obfuscate_param)
+}
+
+/// 计算 API 路径的混淆哈希：`md5(api + "MaimaiChn" + salt)`
+pub fn sdgb_api_hash(api: &str, salt: &str) -> String {
+    let input = format!("{}MaimaiChn{}", api, salt);
     let digest = md5::compute(input.as_bytes());
     format!("{:x}", digest)
 }
 
+/// 标题服务器通讯错误
+#[derive(Debug, thiserror::Error)]
+pub enum TitleServerError {
+    /// 服务器返回了非 200 状态码
+    #[error("title server returned HTTP {status}: {body}")]
+    Http { status: StatusCode, body: String },
+    #[error("title server request timed out")]
+    Timeout,
+    /// 连接失败等其他网络错误
+    #[error("title server transport error: {0}")]
+    Transport(#[source] reqwest::Error),
+    #[error("failed to build title server client: {0}")]
+    Build(#[source] reqwest::Error),
+    /// 请求序列化、压缩或加密失败
+    #[error("failed to encode request: {0}")]
+    Encode(String),
+    #[error("failed to decrypt response: {0}")]
+    Decrypt(String),
+    #[error("failed to decompress response: {0}")]
+    Decompress(#[source] std::io::Error),
+    #[error("response is not valid UTF-8: {0}")]
+    Utf8(#[from] FromUtf8Error),
+    #[error("failed to decode response JSON: {0}")]
+    Decode(#[from] serde_json::Error),
+    /// 响应 JSON 中带有非 0 的 `errorId` / `errorID`
+    #[error("title server returned error id {0}")]
+    ServerErrorId(i64),
+}
+
+impl From<reqwest::Error> for TitleServerError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            TitleServerError::Timeout
+        } else {
+            TitleServerError::Transport(e)
+        }
+    }
+}
+
+impl TitleServerError {
+    /// 是否值得重试：超时、网络错误、429 和 5xx
+    ///
+    /// 解密 / 解压 / 解码失败说明密钥或协议不匹配，重试也不会成功
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TitleServerError::Http { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            TitleServerError::Timeout => true,
+            TitleServerError::Transport(e) => e.is_connect() || e.is_request() || e.is_body(),
+            _ => false,
+        }
+    }
+}
+
+pub type TitleServerResult<T> = std::result::Result<T, TitleServerError>;
 
-/// 舞萌DX 2025 API 通讯用函数
+/// 检查响应 JSON 顶层的 `errorId` / `errorID`
+fn check_server_error_id(text: &str) -> TitleServerResult<()> {
+    let Ok(Value::Object(map)) = serde_json::from_str::<Value>(text) else {
+        return Ok(());
+    };
+    let error_id = map
+        .get("errorId")
+        .or_else(|| map.get("errorID"))
+        .and_then(Value::as_i64)
+        .unwrap_or(0);
+    if error_id != 0 {
+        return Err(TitleServerError::ServerErrorId(error_id));
+    }
+    Ok(())
+}
+
+/// 请求失败时的重试策略，只用于幂等的读取接口，见 [`TitleServerClient::call_idempotent`]
 ///
-/// # 参数
-/// - `data`: 请求数据
-/// - `target_api`: 使用的 API
-/// - `user_agent_extra_data`: UA 附加信息，机台相关则为狗号（如 A63E01E9564），用户相关则为 UID
-/// - `no_log`: 是否不记录日志
-/// - `timeout`: 请求超时时间（秒）
+/// 第 n 次重试前等待 `interval * multiplier^(n-1)`，最长不超过 `max_interval`
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 总尝试次数，包括第一次请求
+    pub max_attempts: u32,
+    pub interval: Duration,
+    pub multiplier: f64,
+    pub max_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            interval: Duration::from_secs(1),
+            multiplier: 1.0,
+            max_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 第 `attempt` 次（从 0 开始）失败后需要等待的时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt as i32);
+        self.interval.mul_f64(factor).min(self.max_interval)
+    }
+}
+
+/// 舞萌DX 2025 标题服务器客户端
 ///
-/// # 返回
-/// 解码后的响应数据
+/// 内部持有一个带连接池的 `reqwest::Client`，应当复用而不是每次请求都重新创建
+#[derive(Clone)]
+pub struct TitleServerClient {
+    client: Client,
+    endpoint: String,
+    cipher: AesPkcs7,
+    hash_salt: String,
+    retry: RetryPolicy,
+}
 
+impl fmt::Debug for TitleServerClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TitleServerClient")
+            .field("endpoint", &self.endpoint)
+            .field("cipher", &self.cipher)
+            .field("hash_salt", &Redacted)
+            .field("retry", &self.retry)
+            .finish_non_exhaustive()
+    }
+}
 
-pub async fn api_sbga(
-    data: Value,
-    target_api: &str,
-    user_agent_extra_data: String,
-) -> Result<String> {
-    init_logger();
-    let data = serde_json::to_string(&data)?;
-    println!("data:{}", data);
-    println!("target_api:{}", target_api);
-    let encrypted_data = AesPkcs7::encrypt(data.as_ref())?;
-    let client = Client::new();
-    let url = format!("{ENDPOINT}{}", get_sdgb_api_hash(target_api));
-
-    let mut headers = HeaderMap::new();
-    let hashed = format!("{}#{}", get_sdgb_api_hash(target_api), user_agent_extra_data);
-    headers.insert(USER_AGENT, hashed.parse()?);
-    headers.insert(CONTENT_TYPE, "application/json".parse()?);
-    headers.insert("Mai-Encoding", "1.50".parse()?);
-    headers.insert("Accept-Encoding", "".parse()?);
-    headers.insert("Charset", "UTF-8".parse()?);
-    headers.insert("Content-Encoding", "deflate".parse()?);
-    headers.insert("Expect", "100-continue".parse()?);
-
-    // 设置最大重试次数和重试间隔
-    let max_retries = 3;
-    let retry_interval = Duration::from_secs(1);
-
-    for attempt in 0..max_retries {
-        let response = client.post(&url)
+pub struct TitleServerClientBuilder {
+    endpoint: String,
+    cipher: AesPkcs7,
+    hash_salt: String,
+    timeout: Duration,
+    retry: RetryPolicy,
+    proxy: Option<String>,
+}
+
+impl Default for TitleServerClientBuilder {
+    fn default() -> Self {
+        Self::from_config(&config::get())
+    }
+}
+
+impl TitleServerClientBuilder {
+    /// 不读取全局配置，直接指定地址和密钥（测试和 mock 服务器使用）
+    pub fn new(endpoint: impl Into<String>, cipher: AesPkcs7, hash_salt: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            cipher,
+            hash_salt: hash_salt.into(),
+            timeout: Duration::from_secs(10),
+            retry: RetryPolicy::default(),
+            proxy: None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.endpoint, AesPkcs7::from_config(config), &config.obfuscate_param)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .proxy(config.use_proxy.then(|| config.proxy_url.clone()))
+    }
+
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    pub fn cipher(mut self, cipher: AesPkcs7) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    pub fn hash_salt(mut self, hash_salt: impl Into<String>) -> Self {
+        self.hash_salt = hash_salt.into();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn build(self) -> TitleServerResult<TitleServerClient> {
+        let mut builder = Client::builder()
+            .timeout(self.timeout)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .tcp_keepalive(Duration::from_secs(60));
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy).map_err(TitleServerError::Build)?);
+        }
+        Ok(TitleServerClient {
+            client: builder.build().map_err(TitleServerError::Build)?,
+            endpoint: self.endpoint,
+            cipher: self.cipher,
+            hash_salt: self.hash_salt,
+            retry: self.retry,
+        })
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_CLIENT: RwLock<Option<Arc<TitleServerClient>>> = RwLock::new(None);
+}
+
+/// `api_sbga` 等自由函数使用的全局客户端，首次使用时按全局配置创建
+pub fn default_client() -> TitleServerResult<Arc<TitleServerClient>> {
+    if let Some(client) = DEFAULT_CLIENT.read().unwrap().as_ref() {
+        return Ok(client.clone());
+    }
+    let client = Arc::new(TitleServerClient::from_config(&config::get())?);
+    *DEFAULT_CLIENT.write().unwrap() = Some(client.clone());
+    Ok(client)
+}
+
+/// 丢弃全局客户端，下次使用时按最新配置重新创建
+pub fn reset_default_client() {
+    *DEFAULT_CLIENT.write().unwrap() = None;
+}
+
+impl TitleServerClient {
+    pub fn builder() -> TitleServerClientBuilder {
+        TitleServerClientBuilder::default()
+    }
+
+    pub fn from_config(config: &Config) -> TitleServerResult<Self> {
+        TitleServerClientBuilder::from_config(config).build()
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn api_hash(&self, api: &str) -> String {
+        sdgb_api_hash(api, &self.hash_salt)
+    }
+
+    /// 舞萌DX 2025 API 通讯，只请求一次
+    ///
+    /// 写入类接口（UpsertUserAllApi、UploadUserPlaylogApi、UserLoginApi 等）超时后服务器可能已经处理过，
+    /// 重试会导致重复写入，因此默认不重试
+    ///
+    /// # 参数
+    /// - `data`: 请求数据
+    /// - `target_api`: 使用的 API
+    /// - `user_agent_extra_data`: UA 附加信息，机台相关则为狗号（如 A63E01E9564），用户相关则为 UID
+    ///
+    /// # 返回
+    /// 解码后的响应数据
+    pub async fn call(
+        &self,
+        data: Value,
+        target_api: &str,
+        user_agent_extra_data: String,
+    ) -> TitleServerResult<String> {
+        self.call_with(data, target_api, user_agent_extra_data, false).await
+    }
+
+    /// 同 [`call`](Self::call)，但 [`TitleServerError::is_retryable`] 的错误会按重试策略重试，只用于幂等的读取接口
+    pub async fn call_idempotent(
+        &self,
+        data: Value,
+        target_api: &str,
+        user_agent_extra_data: String,
+    ) -> TitleServerResult<String> {
+        self.call_with(data, target_api, user_agent_extra_data, true).await
+    }
+
+    #[instrument(name = "title_server", skip_all, fields(api = %target_api, uid = %uid_hash(&user_agent_extra_data)))]
+    pub(crate) async fn call_with(
+        &self,
+        data: Value,
+        target_api: &str,
+        user_agent_extra_data: String,
+        idempotent: bool,
+    ) -> TitleServerResult<String> {
+        let data = serde_json::to_string(&data).map_err(|e| TitleServerError::Encode(e.to_string()))?;
+        debug!(payload = %redact(&data), "request");
+        let encrypted_data = self
+            .cipher
+            .encrypt(data.as_ref())
+            .map_err(|e| TitleServerError::Encode(e.to_string()))?;
+        let api_hash = self.api_hash(target_api);
+        let url = format!("{}{}", self.endpoint, api_hash);
+
+        let mut headers = HeaderMap::new();
+        let hashed = format!("{}#{}", api_hash, user_agent_extra_data);
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(&hashed).map_err(|e| TitleServerError::Encode(e.to_string()))?,
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("Mai-Encoding", HeaderValue::from_static("1.50"));
+        headers.insert("Accept-Encoding", HeaderValue::from_static(""));
+        headers.insert("Charset", HeaderValue::from_static("UTF-8"));
+        headers.insert("Content-Encoding", HeaderValue::from_static("deflate"));
+        headers.insert("Expect", HeaderValue::from_static("100-continue"));
+
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let result = self
+                .send_once(&url, &headers, &encrypted_data)
+                .instrument(tracing::debug_span!("attempt", attempt = attempt + 1))
+                .await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            match result {
+                Ok(content) => {
+                    info!(attempt = attempt + 1, latency_ms, "ok");
+                    return Ok(content);
+                }
+                Err(e) if idempotent && e.is_retryable() && attempt + 1 < self.retry.max_attempts => {
+                    let delay = self.retry.delay(attempt);
+                    warn!(attempt = attempt + 1, latency_ms, error = %e, retry_in = ?delay, "request failed, retrying");
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    warn!(attempt = attempt + 1, latency_ms, error = %e, "request failed");
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// 发送一次请求并解码响应
+    async fn send_once(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        encrypted_data: &[u8],
+    ) -> TitleServerResult<String> {
+        let response = self.client.post(url)
             .headers(headers.clone())  // 克隆 headers
             .body(encrypted_data.to_vec())
             .send()
             .await?;
 
-        println!("{:?}", response);
-
-        // 如果响应状态是 OK，则解密并返回数据
-        if response.status() == StatusCode::OK {
-            let res = response.bytes().await?;
-
-            // 添加解密重试
-            let mut content = Vec::new();
-            let mut decryption_attempt = 0;
-            loop {
-                match AesPkcs7::decrypt(res.as_ref()) {
-                    Ok(decoded_content) => {
-                        content = decoded_content;
-                        break;
-                    }
-                    Err(e) => {
-                        decryption_attempt += 1;
-                        eprintln!("Decryption attempt {} failed: {}", decryption_attempt, e);
-                        if decryption_attempt < max_retries {
-                            println!("Retrying decryption in {} seconds...", retry_interval.as_secs());
-                            sleep(retry_interval).await;
-                        } else {
-                            return Err(anyhow::Error::msg(format!("Decryption failed after {} attempts: {}", max_retries, e)));
-                        }
-                    }
-                }
-            }
-
-            // 解密成功，返回结果
-            match String::from_utf8(content) {
-                Ok(decoded_content) => return Ok(decoded_content),
-                Err(e) => return Err(anyhow::Error::msg(format!("UTF-8 decoding failed: {}", e))),
-            }
-        } else {
-            // 打印错误并判断是否需要重试
-            let error_text = response.text().await?;
-            eprintln!("Request failed (attempt {}): {}", attempt + 1, error_text);
-
-            if attempt + 1 < max_retries {
-                // 如果不是最后一次尝试，等待一段时间然后重试
-                println!("Retrying in {} seconds...", retry_interval.as_secs());
-                sleep(retry_interval).await;
-            } else {
-                // 如果是最后一次尝试，直接返回错误
-                return Err(anyhow::Error::msg(error_text));
-            }
+        let status = response.status();
+        debug!(%status, "response");
+        if status != StatusCode::OK {
+            let body = response.text().await.unwrap_or_default();
+            return Err(TitleServerError::Http { status, body });
         }
+
+        let res = response.bytes().await?;
+        let decrypted = self
+            .cipher
+            .decrypt_raw(res.as_ref())
+            .map_err(|e| TitleServerError::Decrypt(e.to_string()))?;
+        let content = zlib_uncompress(&decrypted).map_err(TitleServerError::Decompress)?;
+        let content = String::from_utf8(content)?;
+        check_server_error_id(&content)?;
+        Ok(content)
     }
+}
 
-    Err(anyhow::Error::msg("Max retries reached"))
+/// 舞萌DX 2025 API 通讯用函数，使用全局默认客户端
+///
+/// 参数含义见 [`TitleServerClient::call`]
+pub async fn api_sbga(
+    data: Value,
+    target_api: &str,
+    user_agent_extra_data: String,
+) -> TitleServerResult<String> {
+    default_client()?.call(data, target_api, user_agent_extra_data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_error_id() {
+        assert!(check_server_error_id(r#"{"userId":1,"errorId":0}"#).is_ok());
+        assert!(check_server_error_id(r#"{"userId":1}"#).is_ok());
+        assert!(matches!(
+            check_server_error_id(r#"{"errorID":100}"#),
+            Err(TitleServerError::ServerErrorId(100))
+        ));
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            interval: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_interval: Duration::from_secs(3),
+        };
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        let http = |code| TitleServerError::Http {
+            status: StatusCode::from_u16(code).unwrap(),
+            body: String::new(),
+        };
+        assert!(http(503).is_retryable());
+        assert!(http(429).is_retryable());
+        assert!(!http(400).is_retryable());
+        assert!(TitleServerError::Timeout.is_retryable());
+        assert!(!TitleServerError::Decrypt("bad padding".to_string()).is_retryable());
+    }
 }
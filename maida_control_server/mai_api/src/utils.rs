@@ -1,44 +1,268 @@
+use chrono::FixedOffset;
+use serde_derive::{Deserialize, Serialize};
 
-pub fn single_ra(achievements: i32, constant: f32) -> i32 {
-    let achievements=achievements as f32 / 10000.0;
-    let ach = achievements.min(100.5);
-    let mul = get_coefficient(ach);
-    ((constant * ach * mul).floor() as i32)
-}
-pub fn get_coefficient(achievements: f32) -> f32 {
-    // 由于 DA 达成率最大 capped 为 100.5%
-    let a = achievements.min(100.5);
-
-    match a {
-        x if x >= 100.5   => 0.224,  // SSS+
-        x if x >= 100.4999=> 0.222,  // SSS
-        x if x >= 100.0   => 0.216,  // SSS
-        x if x >= 99.9999 => 0.214,  // SS+
-        x if x >= 99.5    => 0.211,  // SS+
-        x if x >= 99.0    => 0.208,  // SS
-        x if x >= 98.9999 => 0.206,  // S+
-        x if x >= 98.0    => 0.203,  // S+
-        x if x >= 97.0    => 0.200,  // S
-        x if x >= 96.9999 => 0.176,  // AAA
-        x if x >= 94.0    => 0.168,  // AAA
-        x if x >= 90.0    => 0.152,  // AA
-        x if x >= 80.0    => 0.136,  // A
-        x if x >= 79.9999 => 0.128,  // BBB
-        x if x >= 75.0    => 0.120,  // BBB
-        x if x >= 70.0    => 0.112,  // BB
-        x if x >= 60.0    => 0.096,  // B
-        x if x >= 50.0    => 0.080,  // C
-        x if x >= 40.0    => 0.064,  // D
-        x if x >= 30.0    => 0.048,
-        x if x >= 20.0    => 0.032,
-        x if x >= 10.0    => 0.016,
-        _                 => 0.0,
+/// 达成率上限 100.5%
+pub const MAX_ACHIEVEMENT: i32 = 1005000;
+
+/// 接口返回的宴会场难度下标
+pub(crate) const UTAGE_LEVEL: i32 = 10;
+
+/// 入库时的难度下标，即乐曲数据 `ds` 的下标
+///
+/// 宴会场乐曲只有一张谱面，定数在 `ds[0]`，因此 `achievements` 与 `score_history` 都把 10 存为 0
+pub(crate) fn level_index(level: i32) -> i32 {
+    if level == UTAGE_LEVEL { 0 } else { level }
+}
+
+/// 北京时间（UTC+8），二维码时间和按天统计都以此为准
+pub(crate) fn china_offset() -> FixedOffset {
+    FixedOffset::east_opt(8 * 3600).unwrap()
+}
+
+/// 评级，取值与标题服务器返回的 `scoreRank` 一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[repr(i32)]
+pub enum Rank {
+    D = 0,
+    C,
+    B,
+    BB,
+    BBB,
+    A,
+    AA,
+    AAA,
+    S,
+    #[serde(rename = "S+")]
+    SPlus,
+    SS,
+    #[serde(rename = "SS+")]
+    SSPlus,
+    SSS,
+    #[serde(rename = "SSS+")]
+    SSSPlus,
+}
+
+/// 达成率（×10000）下限、评级、系数（×1000），从高到低排列
+///
+/// 100.4999%、99.9999% 等差 0.0001% 到下一评级的达成率有单独的系数，与游戏一致
+const RANK_TABLE: [(i32, Rank, i64); 23] = [
+    (1005000, Rank::SSSPlus, 224),
+    (1004999, Rank::SSS, 222),
+    (1000000, Rank::SSS, 216),
+    (999999, Rank::SSPlus, 214),
+    (995000, Rank::SSPlus, 211),
+    (990000, Rank::SS, 208),
+    (989999, Rank::SPlus, 206),
+    (980000, Rank::SPlus, 203),
+    (970000, Rank::S, 200),
+    (969999, Rank::AAA, 176),
+    (940000, Rank::AAA, 168),
+    (900000, Rank::AA, 152),
+    (800000, Rank::A, 136),
+    (799999, Rank::BBB, 128),
+    (750000, Rank::BBB, 120),
+    (700000, Rank::BB, 112),
+    (600000, Rank::B, 96),
+    (500000, Rank::C, 80),
+    (400000, Rank::D, 64),
+    (300000, Rank::D, 48),
+    (200000, Rank::D, 32),
+    (100000, Rank::D, 16),
+    (0, Rank::D, 0),
+];
+
+fn rank_row(achievements: i32) -> (i32, Rank, i64) {
+    let a = achievements.min(MAX_ACHIEVEMENT);
+    RANK_TABLE
+        .iter()
+        .copied()
+        .find(|(min, _, _)| a >= *min)
+        .unwrap_or(RANK_TABLE[RANK_TABLE.len() - 1])
+}
+
+impl Rank {
+    pub const ALL: [Rank; 14] = [
+        Rank::D,
+        Rank::C,
+        Rank::B,
+        Rank::BB,
+        Rank::BBB,
+        Rank::A,
+        Rank::AA,
+        Rank::AAA,
+        Rank::S,
+        Rank::SPlus,
+        Rank::SS,
+        Rank::SSPlus,
+        Rank::SSS,
+        Rank::SSSPlus,
+    ];
+
+    pub fn from_achievement(achievements: i32) -> Rank {
+        rank_row(achievements).1
+    }
+
+    /// 达到该评级所需的最低达成率（×10000）
+    pub fn min_achievement(self) -> i32 {
+        RANK_TABLE
+            .iter()
+            .rev()
+            .find(|(_, rank, _)| *rank == self)
+            .map(|(min, _, _)| *min)
+            .unwrap_or_default()
+    }
+
+    /// 高一级的评级，SSS+ 时为 `None`
+    pub fn next(self) -> Option<Rank> {
+        Rank::ALL.get(self as usize + 1).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rank::D => "D",
+            Rank::C => "C",
+            Rank::B => "B",
+            Rank::BB => "BB",
+            Rank::BBB => "BBB",
+            Rank::A => "A",
+            Rank::AA => "AA",
+            Rank::AAA => "AAA",
+            Rank::S => "S",
+            Rank::SPlus => "S+",
+            Rank::SS => "SS",
+            Rank::SSPlus => "SS+",
+            Rank::SSS => "SSS",
+            Rank::SSSPlus => "SSS+",
+        }
+    }
+}
+
+impl std::fmt::Display for Rank {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
     }
 }
 
+/// 定数转为 ×10 的整数，如 13.7 → 137
+fn constant_x10(constant: f32) -> i64 {
+    (constant * 10.0).round() as i64
+}
+
+/// 单曲 rating：`floor(定数 × 达成率% × 系数)`，全程整数运算
+pub fn single_ra(achievements: i32, constant: f32) -> i32 {
+    let a = achievements.clamp(0, MAX_ACHIEVEMENT) as i64;
+    // 定数 ×10、达成率 ×10000、系数 ×1000
+    (constant_x10(constant) * a * get_coefficient(achievements) / 100_000_000) as i32
+}
+
+/// 达成率（×10000）对应的系数（×1000），达成率超过 100.5% 按 100.5% 计
+pub fn get_coefficient(achievements: i32) -> i64 {
+    rank_row(achievements).2
+}
+
 
 #[test]
 fn test(){
     let ra = single_ra(1003272, 13.7);
     print!("{ra}")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_boundaries() {
+        let cases = [
+            (1010000, Rank::SSSPlus, 224),
+            (1005000, Rank::SSSPlus, 224),
+            (1004999, Rank::SSS, 222),
+            (1004998, Rank::SSS, 216),
+            (1000000, Rank::SSS, 216),
+            (999999, Rank::SSPlus, 214),
+            (999998, Rank::SSPlus, 211),
+            (995000, Rank::SSPlus, 211),
+            (994999, Rank::SS, 208),
+            (990000, Rank::SS, 208),
+            (989999, Rank::SPlus, 206),
+            (989998, Rank::SPlus, 203),
+            (980000, Rank::SPlus, 203),
+            (979999, Rank::S, 200),
+            (970000, Rank::S, 200),
+            (969999, Rank::AAA, 176),
+            (969998, Rank::AAA, 168),
+            (940000, Rank::AAA, 168),
+            (939999, Rank::AA, 152),
+            (900000, Rank::AA, 152),
+            (899999, Rank::A, 136),
+            (800000, Rank::A, 136),
+            (799999, Rank::BBB, 128),
+            (799998, Rank::BBB, 120),
+            (750000, Rank::BBB, 120),
+            (749999, Rank::BB, 112),
+            (700000, Rank::BB, 112),
+            (699999, Rank::B, 96),
+            (600000, Rank::B, 96),
+            (599999, Rank::C, 80),
+            (500000, Rank::C, 80),
+            (499999, Rank::D, 64),
+            (400000, Rank::D, 64),
+            (399999, Rank::D, 48),
+            (300000, Rank::D, 48),
+            (299999, Rank::D, 32),
+            (200000, Rank::D, 32),
+            (199999, Rank::D, 16),
+            (100000, Rank::D, 16),
+            (99999, Rank::D, 0),
+            (0, Rank::D, 0),
+            (-1, Rank::D, 0),
+        ];
+        for (achievement, rank, coefficient) in cases {
+            assert_eq!(Rank::from_achievement(achievement), rank, "{achievement}");
+            assert_eq!(get_coefficient(achievement), coefficient, "{achievement}");
+        }
+    }
+
+    #[test]
+    fn rank_min_achievement_and_order() {
+        let mins = [0, 500000, 600000, 700000, 750000, 800000, 900000, 940000, 970000, 980000, 990000, 995000, 1000000, 1005000];
+        for (rank, min) in Rank::ALL.into_iter().zip(mins) {
+            assert_eq!(rank.min_achievement(), min, "{rank}");
+            assert_eq!(Rank::from_achievement(min), rank);
+        }
+        assert!(Rank::ALL.windows(2).all(|w| w[0] < w[1] && w[0].next() == Some(w[1])));
+        assert_eq!(Rank::SSSPlus.next(), None);
+        assert_eq!(serde_json::to_string(&Rank::SSSPlus).unwrap(), "\"SSS+\"");
+    }
+
+    #[test]
+    fn single_ra_matches_game() {
+        let cases = [
+            // 达成率, 定数, rating
+            (1005000, 15.0, 337),
+            (1010000, 15.0, 337),
+            (1005000, 14.4, 324),
+            (1004999, 14.4, 321),
+            (1003272, 13.7, 296),
+            (1000000, 13.7, 295),
+            (999999, 13.7, 293),
+            (995000, 13.7, 287),
+            (990000, 13.7, 282),
+            (970000, 13.7, 265),
+            (969999, 13.7, 233),
+            (800000, 12.0, 130),
+            (0, 15.0, 0),
+        ];
+        for (achievement, constant, ra) in cases {
+            assert_eq!(single_ra(achievement, constant), ra, "{achievement} @ {constant}");
+        }
+    }
+
+    #[test]
+    fn discriminants_are_score_rank() {
+        assert_eq!(Rank::D as i32, 0);
+        assert_eq!(Rank::S as i32, 8);
+        assert_eq!(Rank::SSSPlus as i32, 13);
+        assert_eq!(Rank::from_achievement(1003272) as i32, 12);
+    }
+}
@@ -0,0 +1,297 @@
+//! 舞萌 DX 标题服务器 API 的请求 / 响应结构体
+//!
+//! 只对常用字段做了强类型，其余字段保存在 `extra` 中，方便调试和兼容新版本。
+//! 没有可靠样本的接口仍然使用 `serde_json::Value`，见 `lib.rs` 中 `generate_api_call!` 的说明
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// 只需要 `userId` 的请求体（GetUserPreviewApi / GetUserDataApi / GetUserRatingApi 等）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserIdRequest {
+    pub user_id: i64,
+}
+
+impl UserIdRequest {
+    pub fn new(user_id: i64) -> Self {
+        Self { user_id }
+    }
+}
+
+/// 分页请求体（GetUserMusicApi 等）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserPagedRequest {
+    pub user_id: i64,
+    pub next_index: i64,
+    pub max_count: i64,
+}
+
+/// GetUserPreviewApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserPreview {
+    pub user_id: i64,
+    pub user_name: String,
+    pub is_login: bool,
+    pub last_rom_version: String,
+    pub last_data_version: String,
+    pub last_login_date: String,
+    pub last_play_date: String,
+    pub player_rating: i32,
+    pub nameplate_id: i32,
+    pub icon_id: i32,
+    pub trophy_id: i32,
+    pub is_net_member: i32,
+    pub ban_state: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// GetUserMusicApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserMusicResponse {
+    pub user_id: i64,
+    pub length: i32,
+    /// 下一页的起始下标，为 0 表示已经是最后一页
+    pub next_index: i64,
+    pub user_music_list: Vec<UserMusic>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserMusic {
+    pub user_music_detail_list: Vec<UserMusicDetail>,
+    pub length: i32,
+}
+
+/// 单张谱面的游玩记录
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserMusicDetail {
+    pub music_id: i32,
+    /// 难度下标，0~4 为 Basic~Re:Master，10 为宴会场
+    pub level: i32,
+    pub play_count: i32,
+    /// 达成率 ×10000，例如 100.5% 为 1005000
+    pub achievement: i32,
+    pub combo_status: i32,
+    pub sync_status: i32,
+    pub deluxscore_max: i32,
+    pub score_rank: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// GetUserRatingApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserRatingResponse {
+    pub user_id: i64,
+    pub user_rating: UserRating,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserRating {
+    pub rating: i32,
+    /// 旧版本曲目 B35
+    pub rating_list: Vec<UserRate>,
+    /// 当前版本曲目 B15
+    pub new_rating_list: Vec<UserRate>,
+    pub next_rating_list: Vec<UserRate>,
+    pub next_new_rating_list: Vec<UserRate>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserRate {
+    pub music_id: i32,
+    pub level: i32,
+    pub rom_version: i32,
+    pub achievement: i32,
+}
+
+/// GetUserDataApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserDataResponse {
+    pub user_id: i64,
+    pub user_data: UserData,
+    pub ban_state: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserData {
+    pub user_name: String,
+    pub icon_id: i32,
+    pub plate_id: i32,
+    pub title_id: i32,
+    pub partner_id: i32,
+    pub frame_id: i32,
+    pub player_rating: i32,
+    pub highest_rating: i32,
+    pub grade_rank: i32,
+    pub class_rank: i32,
+    pub course_rank: i32,
+    pub play_count: i32,
+    pub total_deluxscore: i64,
+    pub last_rom_version: String,
+    pub last_data_version: String,
+    pub last_play_date: String,
+    pub last_place_name: String,
+    pub last_region_name: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 乐曲收藏的 `itemKind`
+pub const FAVORITE_ITEM_KIND_MUSIC: i32 = 5;
+
+/// GetUserFavoriteApi 请求体
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserFavoriteRequest {
+    pub user_id: i64,
+    pub item_kind: i32,
+}
+
+/// GetUserFavoriteApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserFavoriteResponse {
+    pub user_id: i64,
+    pub user_favorite_data: UserFavoriteData,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserFavoriteData {
+    pub user_id: i64,
+    pub item_kind: i32,
+    pub item_id_list: Vec<i32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// UserLoginApi 请求体
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserLoginRequest {
+    pub user_id: i64,
+    pub access_code: String,
+    pub region_id: u32,
+    pub place_id: u32,
+    pub client_id: String,
+    /// Unix 时间戳（秒）
+    pub date_time: i64,
+    pub is_continue: bool,
+    pub generic_flag: i32,
+}
+
+/// UserLoginApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserLoginResponse {
+    /// 1 为成功，其他值为登录失败（如账号仍在其他机台上登录）
+    pub return_code: i32,
+    pub last_login_date: String,
+    pub login_count: i32,
+    pub consecutive_login_count: i32,
+    pub login_id: i64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// UserLogoutApi 请求体
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserLogoutRequest {
+    pub user_id: i64,
+    pub access_code: String,
+    pub region_id: u32,
+    pub place_id: u32,
+    pub client_id: String,
+    /// Unix 时间戳（秒）
+    pub date_time: i64,
+    #[serde(rename = "type")]
+    pub logout_type: i32,
+}
+
+/// 只返回 `returnCode` 的接口（UserLogoutApi / UpsertUserAllApi 等）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReturnCodeResponse {
+    /// 1 为成功
+    pub return_code: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ReturnCodeResponse {
+    pub fn is_success(&self) -> bool {
+        self.return_code == 1
+    }
+}
+
+/// GetUserCardApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserCardResponse {
+    pub user_id: i64,
+    pub length: i32,
+    /// 下一页的起始下标，为 0 表示已经是最后一页
+    pub next_index: i64,
+    pub user_card_list: Vec<UserCard>,
+}
+
+/// 功能票 / 旅行券
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserCard {
+    pub card_id: i32,
+    pub card_kind: i32,
+    pub card_type_id: i32,
+    pub chara_id: i32,
+    pub map_id: i32,
+    pub start_date: String,
+    pub end_date: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// GetUserOptionApi
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserOptionResponse {
+    pub user_id: i64,
+    pub user_option: UserOption,
+}
+
+/// 游戏设置，只列出常用的几项
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserOption {
+    pub option_kind: i32,
+    pub note_speed: i32,
+    pub slide_speed: i32,
+    pub touch_speed: i32,
+    pub tap_design: i32,
+    pub hold_design: i32,
+    pub slide_design: i32,
+    pub star_type: i32,
+    pub outline_design: i32,
+    pub note_size: i32,
+    pub slide_size: i32,
+    pub touch_size: i32,
+    pub star_display: i32,
+    pub disp_rate: i32,
+    pub disp_judge: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
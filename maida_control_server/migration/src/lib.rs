@@ -0,0 +1,23 @@
+//! 数据库迁移，MySQL 和 SQLite 通用
+//!
+//! 服务启动时由 `mai_api::database::connect` 自动执行，也可以手动运行：
+//! `cargo run -p migration -- up`（数据库地址取自 `DATABASE_URL`）
+
+pub use sea_orm_migration::prelude::*;
+
+mod m20261018_000001_create_user;
+mod m20261018_000002_create_achievements;
+mod m20261018_000003_create_score_history;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20261018_000001_create_user::Migration),
+            Box::new(m20261018_000002_create_achievements::Migration),
+            Box::new(m20261018_000003_create_score_history::Migration),
+        ]
+    }
+}
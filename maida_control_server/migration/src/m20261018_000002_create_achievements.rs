@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+use super::m20261018_000001_create_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Achievements::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Achievements::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(Achievements::SongId).integer().not_null())
+                    .col(ColumnDef::new(Achievements::Achievement).integer().not_null())
+                    .col(ColumnDef::new(Achievements::Uid).integer().not_null())
+                    .col(ColumnDef::new(Achievements::DxScore).integer().not_null())
+                    .col(ColumnDef::new(Achievements::LevelIndex).integer().not_null())
+                    .col(ColumnDef::new(Achievements::Fc).string().not_null())
+                    .col(ColumnDef::new(Achievements::Fs).string().not_null())
+                    .col(ColumnDef::new(Achievements::Ra).integer().not_null())
+                    .col(ColumnDef::new(Achievements::Constant).float().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Achievements::Table, Achievements::Uid)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // upsert_user_music_detail 的 ON CONFLICT 依赖这个唯一索引
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_achievements_chart")
+                    .table(Achievements::Table)
+                    .col(Achievements::LevelIndex)
+                    .col(Achievements::SongId)
+                    .col(Achievements::Uid)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(Achievements::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Achievements {
+    Table,
+    Id,
+    SongId,
+    /// 列名与表名相同
+    #[sea_orm(iden = "achievements")]
+    Achievement,
+    Uid,
+    DxScore,
+    LevelIndex,
+    Fc,
+    Fs,
+    Ra,
+    Constant,
+}
@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+use super::m20261018_000001_create_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScoreHistory::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(ScoreHistory::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(ScoreHistory::Uid).integer().not_null())
+                    .col(ColumnDef::new(ScoreHistory::SongId).integer().not_null())
+                    .col(ColumnDef::new(ScoreHistory::LevelIndex).integer().not_null())
+                    .col(ColumnDef::new(ScoreHistory::Achievements).integer().not_null())
+                    .col(ColumnDef::new(ScoreHistory::DxScore).integer().not_null())
+                    .col(ColumnDef::new(ScoreHistory::Fc).string().not_null())
+                    .col(ColumnDef::new(ScoreHistory::Fs).string().not_null())
+                    .col(ColumnDef::new(ScoreHistory::ObservedAt).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ScoreHistory::Table, ScoreHistory::Uid)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // 查询都按用户和时间顺序读取
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_score_history_uid_observed_at")
+                    .table(ScoreHistory::Table)
+                    .col(ScoreHistory::Uid)
+                    .col(ScoreHistory::ObservedAt)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(ScoreHistory::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ScoreHistory {
+    Table,
+    Id,
+    Uid,
+    SongId,
+    LevelIndex,
+    Achievements,
+    DxScore,
+    Fc,
+    Fs,
+    ObservedAt,
+}
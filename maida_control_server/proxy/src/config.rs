@@ -0,0 +1,201 @@
+//! 代理与 REST 服务的部署配置
+//!
+//! 默认值对应局域网内开发机的部署方式，可用 `MAI_` 前缀的环境变量覆盖：
+//!
+//! | 环境变量 | 字段 | 默认值 |
+//! |---|---|---|
+//! | `MAI_PROXY_LISTEN` | [`ProxyConfig::listen`] | `0.0.0.0:9854` |
+//! | `MAI_API_LISTEN` | [`ProxyConfig::api_listen`] | `0.0.0.0:9855` |
+//! | `MAI_REDIRECT_BASE` | [`ProxyConfig::redirect_base`] | `http://localhost:5173/home` |
+//! | `MAI_FRONTEND_ORIGIN` | [`ProxyConfig::frontend_origin`] | 不限制 |
+//! | `MAI_INTERCEPT_HOSTS` | [`ProxyConfig::intercept_hosts`]（逗号分隔） | `tgk-wcaime.wahlap.com` |
+//! | `MAI_PASSTHROUGH` | [`ProxyConfig::passthrough`] | `false` |
+//! | `MAI_SESSION_TTL_SECS` | [`ProxyConfig::session_ttl`] | 12 小时 |
+//! | `MAI_CA_CERT` | [`ProxyConfig::ca_cert_path`] | `../certs/local-ca-cert.pem` |
+//! | `MAI_CA_KEY` | [`ProxyConfig::ca_key_path`] | `../certs/local-ca-key.pem` |
+//!
+//! CA 的默认路径相对于 `maida_control_server` 目录（`cargo run` 的工作目录）
+
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Result};
+use crate::session::DEFAULT_SESSION_TTL;
+
+pub const ENV_PREFIX: &str = "MAI_";
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// 代理监听地址
+    pub listen: SocketAddr,
+    /// REST 接口监听地址
+    pub api_listen: SocketAddr,
+    /// 登录完成后浏览器跳转到的前端页面，令牌以 `#session=<令牌>` 附在后面
+    pub redirect_base: String,
+    /// 允许跨域访问 REST 接口的前端来源（如 `https://maida.example.com`），`None` 时不限制
+    pub frontend_origin: Option<String>,
+    /// 需要拦截的主机：`CONNECT` 时终止 TLS，普通 HTTP 请求与隧道内的请求同样处理（捕获登录，其余改用 https 转发）
+    pub intercept_hosts: Vec<String>,
+    /// 其他主机原样转发（`CONNECT` 直接对接字节流），关闭时返回 403。
+    /// 开启后也只转发到 80/443 端口的公网地址，代理不会成为能访问内网的开放中继。
+    /// 使用 PAC 时只有登录主机经过代理，不需要开启；手动填写 Wi-Fi 代理时需要开启，否则微信等其他应用会无法联网
+    pub passthrough: bool,
+    /// 捕获登录后签发的会话有效期
+    pub session_ttl: Duration,
+    /// 签发拦截证书用的 CA 证书和私钥（PEM）
+    pub ca_cert_path: PathBuf,
+    pub ca_key_path: PathBuf,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 9854)),
+            api_listen: SocketAddr::from(([0, 0, 0, 0], 9855)),
+            redirect_base: "http://localhost:5173/home".to_string(),
+            frontend_origin: None,
+            intercept_hosts: vec!["tgk-wcaime.wahlap.com".to_string()],
+            passthrough: false,
+            session_ttl: DEFAULT_SESSION_TTL,
+            ca_cert_path: PathBuf::from("../certs/local-ca-cert.pem"),
+            ca_key_path: PathBuf::from("../certs/local-ca-key.pem"),
+        }
+    }
+}
+
+fn env_var(key: &str) -> Option<String> {
+    env::var(format!("{ENV_PREFIX}{key}")).ok().map(|v| v.trim().to_string())
+}
+
+impl ProxyConfig {
+    /// 默认值叠加 `MAI_` 环境变量，然后校验
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Some(v) = env_var("PROXY_LISTEN") {
+            config.listen = v.parse().map_err(|e| anyhow!("MAI_PROXY_LISTEN: {e}"))?;
+        }
+        if let Some(v) = env_var("API_LISTEN") {
+            config.api_listen = v.parse().map_err(|e| anyhow!("MAI_API_LISTEN: {e}"))?;
+        }
+        if let Some(v) = env_var("REDIRECT_BASE") {
+            config.redirect_base = v;
+        }
+        if let Some(v) = env_var("FRONTEND_ORIGIN") {
+            config.frontend_origin = Some(v).filter(|v| !v.is_empty());
+        }
+        if let Some(v) = env_var("INTERCEPT_HOSTS") {
+            config.intercept_hosts = v.split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| !h.is_empty()).collect();
+        }
+        if let Some(v) = env_var("PASSTHROUGH") {
+            config.passthrough = v.parse().map_err(|e| anyhow!("MAI_PASSTHROUGH: {e}"))?;
+        }
+        if let Some(v) = env_var("SESSION_TTL_SECS") {
+            config.session_ttl = Duration::from_secs(v.parse().map_err(|e| anyhow!("MAI_SESSION_TTL_SECS: {e}"))?);
+        }
+        if let Some(v) = env_var("CA_CERT") {
+            config.ca_cert_path = PathBuf::from(v);
+        }
+        if let Some(v) = env_var("CA_KEY") {
+            config.ca_key_path = PathBuf::from(v);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !is_http_url(&self.redirect_base) {
+            bail!("redirect_base 必须是 http(s) 地址: {}", self.redirect_base);
+        }
+        if let Some(origin) = &self.frontend_origin
+            && (!is_http_url(origin) || origin.ends_with('/'))
+        {
+            bail!("frontend_origin 必须形如 https://host[:port]: {origin}");
+        }
+        if self.intercept_hosts.is_empty() {
+            bail!("intercept_hosts 不能为空");
+        }
+        if self.session_ttl.is_zero() {
+            bail!("session_ttl 必须大于 0");
+        }
+        Ok(())
+    }
+
+    /// 登录完成后的跳转地址，令牌放在 fragment 中，不会发给前端服务器，也不会出现在 Referer 里
+    pub fn redirect_url(&self, token: &str) -> String {
+        format!("{}#session={token}", self.redirect_base)
+    }
+
+    /// 本机访问 REST 接口的地址，监听在全部地址上时使用回环地址
+    pub fn local_api_addr(&self) -> SocketAddr {
+        let ip = match self.api_listen.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        SocketAddr::new(ip, self.api_listen.port())
+    }
+
+    /// `host` 可以带端口
+    pub fn should_intercept(&self, host: &str) -> bool {
+        let host = host_name(host);
+        self.intercept_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+    }
+}
+
+/// 去掉端口和 IPv6 地址的方括号：`example.com:443` -> `example.com`，`[::1]:443` -> `::1`
+pub fn host_name(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split_once(']').map_or(rest, |(ip, _)| ip);
+    }
+    match host.split_once(':') {
+        // 没有方括号的 IPv6 地址不会带端口
+        Some((name, port)) if !port.contains(':') => name,
+        _ => host,
+    }
+}
+
+fn is_http_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = ProxyConfig::default();
+        config.validate().unwrap();
+        assert_eq!(config.redirect_url("abc"), "http://localhost:5173/home#session=abc");
+        assert!(config.should_intercept("tgk-wcaime.wahlap.com"));
+        assert!(config.should_intercept("TGK-WCAIME.wahlap.com:443"));
+        assert!(!config.should_intercept("example.com"));
+        assert!(!config.passthrough);
+        assert!(!config.should_intercept("[::1]:443"));
+        assert_eq!(config.local_api_addr(), SocketAddr::from(([127, 0, 0, 1], 9855)));
+    }
+
+    #[test]
+    fn host_name_strips_port() {
+        assert_eq!(host_name("example.com"), "example.com");
+        assert_eq!(host_name("example.com:443"), "example.com");
+        assert_eq!(host_name("[::1]:443"), "::1");
+        assert_eq!(host_name("[::1]"), "::1");
+        assert_eq!(host_name("::1"), "::1");
+        assert_eq!(host_name("2001:db8::1"), "2001:db8::1");
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let bad = [
+            ProxyConfig { redirect_base: "192.168.10.9/home".to_string(), ..Default::default() },
+            ProxyConfig { frontend_origin: Some("https://maida.example.com/".to_string()), ..Default::default() },
+            ProxyConfig { intercept_hosts: Vec::new(), ..Default::default() },
+            ProxyConfig { session_ttl: Duration::ZERO, ..Default::default() },
+        ];
+        for config in bad {
+            assert!(config.validate().is_err(), "{config:?}");
+        }
+    }
+}
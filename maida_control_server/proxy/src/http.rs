@@ -0,0 +1,274 @@
+//! 隧道内的 HTTP/1.1 请求读取
+//!
+//! 终止 TLS 后浏览器发来的是原始字节流，[`RequestReader`] 负责把它切分成一个个请求：
+//! 请求头可能分多次到达，请求体按 `Content-Length` 或 chunked 读取，同一连接上可以有多个请求（keep-alive）
+
+use hyper::StatusCode;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// 请求行加请求头的最大长度，超过时返回 431
+pub const MAX_HEADER_BYTES: usize = 16 * 1024;
+pub const MAX_HEADERS: usize = 64;
+/// 请求体的最大长度，超过时返回 413
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+const READ_CHUNK: usize = 8 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error("请求头过大")]
+    HeadersTooLarge,
+    #[error("请求体过大")]
+    BodyTooLarge,
+    #[error("请求格式错误: {0}")]
+    Malformed(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl ReadError {
+    /// 出错时回给客户端的状态码，连接已断开时为 `None`
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ReadError::HeadersTooLarge => Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            ReadError::BodyTooLarge => Some(StatusCode::PAYLOAD_TOO_LARGE),
+            ReadError::Malformed(_) => Some(StatusCode::BAD_REQUEST),
+            ReadError::Io(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedRequest {
+    pub method: String,
+    pub path: String,
+    /// `1` 表示 HTTP/1.1，`0` 表示 HTTP/1.0
+    pub version: u8,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ParsedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn header_has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    }
+
+    /// HTTP/1.1 默认保持连接，HTTP/1.0 需要显式 `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        if self.version >= 1 {
+            !self.header_has_token("connection", "close")
+        } else {
+            self.header_has_token("connection", "keep-alive")
+        }
+    }
+}
+
+pub struct RequestReader<S> {
+    stream: S,
+    buf: Vec<u8>,
+}
+
+impl<S: AsyncRead + Unpin> RequestReader<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream, buf: Vec::new() }
+    }
+
+    /// 写响应时要用到底层连接
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// 读取下一个完整的请求，连接在两个请求之间正常关闭时返回 `None`
+    pub async fn next_request(&mut self) -> Result<Option<ParsedRequest>, ReadError> {
+        let Some(mut request) = self.read_head().await? else {
+            return Ok(None);
+        };
+        request.body = if request.header_has_token("transfer-encoding", "chunked") {
+            self.read_chunked().await?
+        } else if let Some(length) = request.header("content-length") {
+            let length: usize = length
+                .trim()
+                .parse()
+                .map_err(|_| ReadError::Malformed(format!("Content-Length: {length}")))?;
+            if length > MAX_BODY_BYTES {
+                return Err(ReadError::BodyTooLarge);
+            }
+            self.take(length).await?
+        } else {
+            Vec::new()
+        };
+        Ok(Some(request))
+    }
+
+    async fn read_head(&mut self) -> Result<Option<ParsedRequest>, ReadError> {
+        loop {
+            if !self.buf.is_empty() {
+                let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+                let mut req = httparse::Request::new(&mut headers);
+                match req.parse(&self.buf) {
+                    Ok(httparse::Status::Complete(len)) => {
+                        let request = ParsedRequest {
+                            method: req.method.unwrap_or_default().to_string(),
+                            path: req.path.unwrap_or_default().to_string(),
+                            version: req.version.unwrap_or(1),
+                            headers: req
+                                .headers
+                                .iter()
+                                .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).into_owned()))
+                                .collect(),
+                            body: Vec::new(),
+                        };
+                        self.buf.drain(..len);
+                        return Ok(Some(request));
+                    }
+                    Ok(httparse::Status::Partial) => {}
+                    Err(httparse::Error::TooManyHeaders) => return Err(ReadError::HeadersTooLarge),
+                    Err(e) => return Err(ReadError::Malformed(e.to_string())),
+                }
+                if self.buf.len() >= MAX_HEADER_BYTES {
+                    return Err(ReadError::HeadersTooLarge);
+                }
+            }
+            if self.fill().await? == 0 {
+                return if self.buf.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(ReadError::Malformed("请求头未结束连接就关闭了".to_string()))
+                };
+            }
+        }
+    }
+
+    async fn read_chunked(&mut self) -> Result<Vec<u8>, ReadError> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.take_line().await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| ReadError::Malformed(format!("chunk size: {size}")))?;
+            if size == 0 {
+                // 跳过 trailer，直到空行
+                while !self.take_line().await?.is_empty() {}
+                return Ok(body);
+            }
+            if body.len() + size > MAX_BODY_BYTES {
+                return Err(ReadError::BodyTooLarge);
+            }
+            body.extend(self.take(size).await?);
+            if !self.take_line().await?.is_empty() {
+                return Err(ReadError::Malformed("chunk 后缺少 CRLF".to_string()));
+            }
+        }
+    }
+
+    /// 取出一行（不含 CRLF）
+    async fn take_line(&mut self) -> Result<String, ReadError> {
+        loop {
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buf[..pos]).into_owned();
+                self.buf.drain(..pos + 2);
+                return Ok(line);
+            }
+            if self.buf.len() >= MAX_HEADER_BYTES {
+                return Err(ReadError::Malformed("chunk 行过长".to_string()));
+            }
+            self.fill_or_eof().await?;
+        }
+    }
+
+    async fn take(&mut self, len: usize) -> Result<Vec<u8>, ReadError> {
+        while self.buf.len() < len {
+            self.fill_or_eof().await?;
+        }
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    async fn fill(&mut self) -> Result<usize, ReadError> {
+        let mut chunk = [0u8; READ_CHUNK];
+        let n = self.stream.read(&mut chunk).await?;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+
+    async fn fill_or_eof(&mut self) -> Result<(), ReadError> {
+        match self.fill().await? {
+            0 => Err(ReadError::Malformed("请求体未结束连接就关闭了".to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    /// 把 `parts` 分多次写入，模拟分包到达
+    fn split_stream(parts: Vec<Vec<u8>>) -> tokio::io::DuplexStream {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            for part in parts {
+                server.write_all(&part).await.unwrap();
+                server.flush().await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn headers_split_across_reads() {
+        let stream = split_stream(vec![
+            b"GET /wc_auth/oauth/callback/maimai-dx?code=1 HTTP/1.1\r\nHo".to_vec(),
+            b"st: tgk-wcaime.wahlap.com\r\nCookie: a=b\r\n".to_vec(),
+            b"\r\n".to_vec(),
+        ]);
+        let mut reader = RequestReader::new(stream);
+        let request = reader.next_request().await.unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/wc_auth/oauth/callback/maimai-dx?code=1");
+        assert_eq!(request.header("host"), Some("tgk-wcaime.wahlap.com"));
+        assert!(request.keep_alive());
+        assert!(reader.next_request().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn keep_alive_with_bodies() {
+        let input: &[u8] = b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n4;ext=1\r\nbarz\r\n0\r\nX-Trailer: 1\r\n\r\n\
+GET /c HTTP/1.0\r\n\r\n";
+        let mut reader = RequestReader::new(input);
+        let a = reader.next_request().await.unwrap().unwrap();
+        assert_eq!((a.path.as_str(), a.body.as_slice()), ("/a", &b"hello"[..]));
+        let b = reader.next_request().await.unwrap().unwrap();
+        assert_eq!((b.path.as_str(), b.body.as_slice()), ("/b", &b"foobarz"[..]));
+        let c = reader.next_request().await.unwrap().unwrap();
+        assert_eq!(c.path, "/c");
+        assert!(!c.keep_alive());
+        assert!(reader.next_request().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_or_malformed_requests() {
+        let mut big = b"GET / HTTP/1.1\r\n".to_vec();
+        while big.len() <= MAX_HEADER_BYTES {
+            big.extend_from_slice(b"X-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n");
+        }
+        let err = RequestReader::new(big.as_slice()).next_request().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+
+        let input = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_BYTES + 1);
+        let err = RequestReader::new(input.as_bytes()).next_request().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+
+        let err = RequestReader::new(&b"GET / HTTP/1.1\r\nHost"[..]).next_request().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::BAD_REQUEST));
+    }
+}
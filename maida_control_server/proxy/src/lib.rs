@@ -1,194 +1,320 @@
+pub mod config;
+pub mod http;
 mod maimai;
+pub mod pac;
+pub mod response;
+pub mod session;
+pub mod tls;
 
 use std::convert::Infallible;
-use std::net::SocketAddr;
-use hyper::{Body, Request, Response, Server, Method, Error};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use hyper::{Body, Request, Response, Server, Method, StatusCode};
 use hyper::body::to_bytes;
+use hyper::upgrade::Upgraded;
 use hyper::http::header;
+use hyper::http::uri::{Authority, Scheme};
 use reqwest::header as reqwest_header;
 use hyper::service::{make_service_fn, service_fn};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use std::io::Write;
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
 use reqwest::redirect::Policy;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use crate::maimai::{get_open_url, maimai_handle};
-use anyhow::{anyhow, Result};
+use crate::config::{host_name, ProxyConfig};
+use crate::session::{Session, SessionStore};
+use crate::http::{ParsedRequest, RequestReader};
+use crate::response::RawResponse;
+use crate::tls::CertAuthority;
+use anyhow::Result;
+pub use crate::maimai::LoginResponse;
 
-async fn handle_proxy_request(mut req: Request<Body>) -> Result<Response<Body>> {
-    if Method::CONNECT == req.method() {
-        let host_with_port = req.uri().authority().map(|a| a.to_string()).unwrap_or_default();
+/// 舞萌 DX 网页的 OAuth 授权入口
+pub const AUTHORIZE_URL: &str = "https://tgk-wcaime.wahlap.com/wc_auth/oauth/authorize/maimai-dx";
+const AUTHORIZE_PATH: &str = "/wc_auth/oauth/authorize/maimai-dx";
+/// 授权后微信跳转到的回调地址，带有登录用的 `code`
+const CALLBACK_PATH: &str = "/wc_auth/oauth/callback/maimai-dx";
+
+/// 经过代理转发的请求都带有这个 `Via` 头，服务端据此判断请求是否来自代理
+pub const VIA: &str = "1.1 maida-proxy";
+
+/// 手机设置页的自检地址，PAC 让它经过代理，代理总是把它转发给本机的 REST 服务
+pub const SETUP_CHECK_PATH: &str = "/setup/check";
+
+/// 直通时允许连接的上游端口
+const PASSTHROUGH_PORTS: [u16; 2] = [80, 443];
+
+/// 取得微信授权页地址，授权后浏览器经过代理访问回调地址时会被拦截并捕获登录
+pub async fn authorize_url() -> Result<String> {
+    get_open_url(&AUTHORIZE_URL.to_string()).await
+}
+
+/// 代理各连接共享的状态
+struct ProxyState {
+    config: Arc<ProxyConfig>,
+    sessions: Arc<dyn SessionStore>,
+    ca: CertAuthority,
+    /// 转发用的客户端，不跟随重定向
+    client: reqwest::Client,
+    /// 直通时检查端口和地址，只有测试中会关闭
+    check_targets: bool,
+}
+
+impl ProxyState {
+    fn new(config: Arc<ProxyConfig>, sessions: Arc<dyn SessionStore>) -> Result<Self> {
+        let ca = CertAuthority::load(&config.ca_cert_path, &config.ca_key_path)?;
+        let client = reqwest::Client::builder().redirect(Policy::none()).no_proxy().build()?;
+        Ok(Self { config, sessions, ca, client, check_targets: true })
+    }
+}
+
+fn plain_response(status: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder().status(status).body(Body::from(body)).unwrap()
+}
+
+/// 是否为公网地址，直通时拒绝回环、内网、链路本地等地址
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // 0.0.0.0/8
+                || a == 0
+                // 100.64.0.0/10 运营商级 NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // fc00::/7 唯一本地地址
+                    || (first & 0xfe00) == 0xfc00
+                    // fe80::/10 链路本地地址
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// 解析直通目标并检查，不允许时返回 403，解析失败时返回 502
+///
+/// 之后直接连接返回的地址，不再重新解析，避免检查后被 DNS 换成内网地址
+async fn passthrough_addrs(state: &ProxyState, host: &str, port: u16) -> Result<Vec<SocketAddr>, StatusCode> {
+    if state.check_targets && !PASSTHROUGH_PORTS.contains(&port) {
+        debug!(port, "passthrough port not allowed");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let host = host_name(host);
+    let addrs: Vec<SocketAddr> = match lookup_host((host, port)).await {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => {
+            warn!(error = %e, "解析上游地址失败");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+    if addrs.is_empty() {
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+    if state.check_targets && !addrs.iter().all(|addr| is_public_ip(addr.ip())) {
+        debug!("passthrough to non-public address refused");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(addrs)
+}
+
+/// 读出请求体，读取失败（客户端中途断开、chunked 格式错误等）时返回 400
+async fn read_body(req: &mut Request<Body>) -> Result<Vec<u8>, Response<Body>> {
+    match to_bytes(req.body_mut()).await {
+        Ok(body) => Ok(body.to_vec()),
+        Err(e) => {
+            debug!(error = %e, "read request body failed");
+            Err(plain_response(StatusCode::BAD_REQUEST, "cannot read request body"))
+        }
+    }
+}
+
+fn refused(status: StatusCode) -> Response<Body> {
+    if status == StatusCode::FORBIDDEN {
+        plain_response(status, "blocked: host not allowed")
+    } else {
+        plain_response(status, "bad gateway")
+    }
+}
+
+/// 逐跳头部，不转发给上游
+fn is_hop_by_hop(name: &str) -> bool {
+    ["host", "connection", "content-length", "proxy-connection", "keep-alive", "transfer-encoding", "upgrade"]
+        .iter()
+        .any(|h| name.eq_ignore_ascii_case(h))
+}
 
-        if host_with_port.is_empty() {
-            return Ok(Response::builder().status(400).body(Body::from("CONNECT request missing authority")).unwrap());
+/// 把请求原样转发给上游
+async fn forward<'a>(
+    client: &reqwest::Client,
+    method: &str,
+    url: &str,
+    headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    body: Vec<u8>,
+) -> Result<reqwest::Response> {
+    let method = reqwest::Method::from_bytes(method.as_bytes())?;
+    let mut rb = client.request(method, url).body(body);
+    for (name, value) in headers {
+        if is_hop_by_hop(name) {
+            continue;
         }
+        let Ok(rn) = reqwest_header::HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        let Ok(rv) = reqwest_header::HeaderValue::from_bytes(value) else {
+            continue;
+        };
+        rb = rb.header(rn, rv);
+    }
+    Ok(rb.header(reqwest_header::VIA, VIA).send().await?)
+}
+
+async fn handle_proxy_request(mut req: Request<Body>, state: Arc<ProxyState>) -> Result<Response<Body>> {
+    if Method::CONNECT == req.method() {
+        let Some(authority) = req.uri().authority().cloned() else {
+            return Ok(plain_response(StatusCode::BAD_REQUEST, "CONNECT request missing authority"));
+        };
+        let host_with_port = authority.to_string();
+        let intercept = state.config.should_intercept(&host_with_port);
+        let upstream = if intercept {
+            Vec::new()
+        } else if !state.config.passthrough {
+            return Ok(refused(StatusCode::FORBIDDEN));
+        } else {
+            match passthrough_addrs(&state, authority.host(), authority.port_u16().unwrap_or(443)).await {
+                Ok(addrs) => addrs,
+                Err(status) => return Ok(refused(status)),
+            }
+        };
+        let span = info_span!("connect", target = %host_with_port, intercept);
         tokio::spawn(async move {
-            match hyper::upgrade::on(&mut req).await {
-                Ok(mut client_stream) => {
-                    println!("[CONNECT] 隧道请求建立，目标: {}", host_with_port);
-
-                    let mut buffer = [0; 4096];
-                    let n = match client_stream.read(&mut buffer).await {
-                        Ok(0) | Err(_) => return ,
-                        Ok(n) => n,
-                    };
-
-                    let mut headers = [httparse::EMPTY_HEADER; 64];
-                    let mut req = httparse::Request::new(&mut headers);
-
-                    if req.parse(&buffer[..n]).is_ok() {
-                        if let (Some(method_str), Some(path)) = (req.method, req.path) {
-                            let host_name = host_with_port.split(':').next().unwrap_or(&host_with_port);
-                            let full_url = format!("https://{}{}", host_name, path);
-                            println!("path:{path}");
-                            if path=="/wc_auth/oauth/authorize/maimai-dx" {
-                                match get_open_url(&full_url).await {
-                                    Ok(location) => {
-                                        // 拼 302 响应
-                                        let response = format!(
-                                            "HTTP/1.1 302 Found\r\n\
-            Location: {}\r\n\
-            Content-Length: 0\r\n\
-            Connection: close\r\n\
-            \r\n",
-                                            location
-                                        );
-                                        client_stream.write_all(response.as_bytes()).await.unwrap();
-                                    }
-                                    Err(e) => {
-                                        let html_body = format!(r#"<!DOCTYPE html><html lang="en">
-<head><meta charset="UTF-8"><title>Error</title></head>
-<body><h1>代理错误</h1><p>{}</p></body>
-</html>"#, e);
-
-                                        let response = format!(
-                                            "HTTP/1.1 502 Bad Gateway\r\n\
-            Content-Type: text/html; charset=utf-8\r\n\
-            Content-Length: {}\r\n\
-            Connection: close\r\n\
-            \r\n\
-            {}",
-                                            html_body.len(),
-                                            html_body
-                                        );
-                                        client_stream.write_all(response.as_bytes()).await.unwrap();
-                                    }
-                                }
-                            }else{
-                                let res= maimai_handle(full_url,req.headers).await;
-                                match res {
-                                    Ok((login,open_user_id))=>{
-                                        let redirect_url = format!(
-                                            "http://192.168.10.9:5173/home?user_id={}&open_game_id={}&session_id={}&user_play_flag={}&new_user_id_flag={}&open_game_id_flag={}&open_user_id={}",
-                                            login.user_id,
-                                            login.open_game_id,
-                                            login.session_id,
-                                            login.user_play_flag,
-                                            login.new_user_id_flag,
-                                            login.open_game_id_flag,
-                                            open_user_id
-
-                                        );
-                                        println!("{}", redirect_url);
-                                        let response = format!(
-                                            "HTTP/1.1 302 Found\r\n\
-    Location: {}\r\n\
-    Content-Length: 0\r\n\
-    Connection: close\r\n\
-    \r\n",
-                                            redirect_url
-                                        );
-                                        client_stream.write_all(response.as_bytes()).await.unwrap();
-                                        println!("---------------------------------------\n");
-                                    },
-                                    Err(e)=>{
-                                        let html_body = format!(r#"<!DOCTYPE html><html lang="en">
-                            <head>
-                                <meta charset="UTF-8">
-                                <title>MaiDaControl</title>
-                            </head>
-                            <body>
-                                <h1>MaiDaControl Error:</h1>
-                                <p>{:?}</p>
-                            </body>
-                            </html>"#,e);
-
-                                        let response = format!( "HTTP/1.1 200 OK\r\n\
-    Content-Type: text/html; charset=utf-8\r\n\
-    Content-Length: {}\r\n\
-    Connection: close\r\n\
-    \r\n\
-    {}",html_body.len(),html_body);
-                                        client_stream.write_all(response.as_bytes()).await.unwrap();
-
-
-                                    }
-                                }
-                            }
-
-
-                        }
+            let upgraded = match hyper::upgrade::on(&mut req).await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    warn!(error = %e, "升级连接失败");
+                    return;
+                }
+            };
+            if !intercept {
+                tunnel(upgraded, &upstream).await;
+                return;
+            }
+            let host_name = host_name(authority.host()).to_string();
+            let stream = match state.ca.acceptor(&host_name) {
+                Ok(acceptor) => match acceptor.accept(upgraded).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        // 多半是手机没有信任本地 CA
+                        warn!(error = %e, "TLS 握手失败");
+                        return;
                     }
                 },
-                Err(e) => eprintln!("[CONNECT] 升级连接失败: {}", e),
-            }
-        });
+                Err(e) => {
+                    error!(error = ?e, "签发证书失败");
+                    return;
+                }
+            };
+            info!("隧道请求建立");
+            handle_intercepted(stream, &host_name, &state).await;
+        }.instrument(span));
 
 
         Ok(Response::builder().status(200).body(Body::empty()).unwrap())
     } else {
-        let host = req
-            .headers()
-            .get(header::HOST)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or_default()
-            .to_string();
-        if host != "tgk-wcaime.wahlap.com" {
-            return Ok(Response::builder()
-                .status(403)
-                .body(Body::from("blocked: host not allowed"))
-                .unwrap());
-        }
-
+        let authority = match req.uri().authority() {
+            Some(authority) => Some(authority.clone()),
+            None => req
+                .headers()
+                .get(header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<Authority>().ok()),
+        };
+        let Some(authority) = authority else {
+            return Ok(plain_response(StatusCode::BAD_REQUEST, "request missing host"));
+        };
+        let host = authority.as_str();
         let path_and_query = req
             .uri()
             .path_and_query()
             .map(|pq| pq.as_str())
             .unwrap_or("/");
-        let target_url = format!("https://tgk-wcaime.wahlap.com{}", path_and_query);
-        println!("[HTTP] forward {} {}", req.method(), target_url);
-
-        let body = to_bytes(req.body_mut()).await.unwrap_or_default();
-        let client = reqwest::Client::builder()
-            .redirect(Policy::none())
-            .build()?;
-        let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes())?;
-        let mut rb = client.request(method, &target_url).body(body.to_vec());
-
-        for (name, value) in req.headers().iter() {
-            if name == header::HOST
-                || name == header::CONNECTION
-                || name == header::CONTENT_LENGTH
-                || name.as_str().eq_ignore_ascii_case("proxy-connection")
-            {
-                continue;
-            }
-            let Ok(rn) = reqwest_header::HeaderName::from_bytes(name.as_str().as_bytes()) else {
-                continue;
+        if state.config.should_intercept(host) {
+            // 与隧道内的请求同样处理，明文访问授权入口和回调地址时也会捕获登录
+            let path = path_and_query.to_string();
+            let body = match read_body(&mut req).await {
+                Ok(body) => body,
+                Err(response) => return Ok(response),
             };
-            let Ok(rv) = reqwest_header::HeaderValue::from_bytes(value.as_bytes()) else {
-                continue;
+            let request = ParsedRequest {
+                method: req.method().to_string(),
+                path,
+                version: if req.version() == hyper::Version::HTTP_10 { 0 } else { 1 },
+                headers: req
+                    .headers()
+                    .iter()
+                    .map(|(n, v)| (n.to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+                    .collect(),
+                body,
             };
-            rb = rb.header(rn, rv);
+            return Ok(intercepted_response(request, host_name(authority.host()), &state).await.into_response());
         }
+        let (client, target_url) = if req.uri().path() == SETUP_CHECK_PATH && authority.port_u16() == Some(state.config.api_listen.port()) {
+            // 自检地址是本机的局域网地址，直接转给本机的 REST 服务，不需要开启直通
+            (state.client.clone(), format!("http://{}{}", state.config.local_api_addr(), path_and_query))
+        } else if state.config.passthrough {
+            let default_port = if req.uri().scheme() == Some(&Scheme::HTTPS) { 443 } else { 80 };
+            let addrs = match passthrough_addrs(&state, authority.host(), authority.port_u16().unwrap_or(default_port)).await {
+                Ok(addrs) => addrs,
+                Err(status) => return Ok(refused(status)),
+            };
+            // 连接检查过的地址
+            let client = reqwest::Client::builder()
+                .redirect(Policy::none())
+                .no_proxy()
+                .resolve_to_addrs(host_name(authority.host()), &addrs)
+                .build()?;
+            // 代理请求的 URI 是绝对形式，原样转发
+            let url = match req.uri().scheme() {
+                Some(_) => req.uri().to_string(),
+                None => format!("http://{}{}", host, path_and_query),
+            };
+            (client, url)
+        } else {
+            return Ok(refused(StatusCode::FORBIDDEN));
+        };
+        // 查询串里可能有令牌，只记录主机和路径
+        info!(method = %req.method(), %host, path = req.uri().path(), "forward");
 
-        let res = rb.send().await?;
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(response) => return Ok(response),
+        };
+        let headers = req.headers().iter().map(|(n, v)| (n.as_str(), v.as_bytes()));
+        let res = match forward(&client, req.method().as_str(), &target_url, headers, body).await {
+            Ok(res) => res,
+            Err(e) => {
+                warn!(error = %e, "forward failed");
+                return Ok(refused(StatusCode::BAD_GATEWAY));
+            }
+        };
         let status = res.status();
         let mut builder = Response::builder().status(
             hyper::StatusCode::from_u16(status.as_u16()).unwrap_or(hyper::StatusCode::BAD_GATEWAY),
         );
         for (name, value) in res.headers().iter() {
-            if name.as_str().eq_ignore_ascii_case("transfer-encoding")
-                || name.as_str().eq_ignore_ascii_case("content-length")
-            {
+            if is_hop_by_hop(name.as_str()) {
                 continue;
             }
             let Ok(hn) = hyper::http::HeaderName::from_bytes(name.as_str().as_bytes()) else {
@@ -199,20 +325,321 @@ async fn handle_proxy_request(mut req: Request<Body>) -> Result<Response<Body>>
             };
             builder = builder.header(hn, hv);
         }
-        let bytes = res.bytes().await?;
+        let bytes = match res.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!(error = %e, "read upstream response failed");
+                return Ok(refused(StatusCode::BAD_GATEWAY));
+            }
+        };
         Ok(builder.body(Body::from(bytes))?)
     }
 }
 
 
-pub async fn service() {
-    let addr = SocketAddr::from(([0, 0, 0, 0], 9854));
-    let make_svc = make_service_fn(|_conn| async {
-        Ok::<_, Infallible>(service_fn(handle_proxy_request))
+/// 不拦截的主机，在客户端和上游之间原样转发，`target` 为检查过的上游地址
+async fn tunnel(mut client: Upgraded, target: &[SocketAddr]) {
+    let mut upstream = match TcpStream::connect(target).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            warn!(error = %e, "连接上游失败");
+            return;
+        }
+    };
+    match copy_bidirectional(&mut client, &mut upstream).await {
+        Ok((up, down)) => debug!(up, down, "tunnel closed"),
+        Err(e) => debug!(error = %e, "tunnel closed with error"),
+    }
+}
+
+/// 处理已终止 TLS 的拦截连接，每个请求交给 [`intercepted_response`]
+async fn handle_intercepted<S>(client_stream: S, host_name: &str, state: &ProxyState)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = RequestReader::new(client_stream);
+    loop {
+        let request = match reader.next_request().await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                debug!(error = %e, "read tunnel request failed");
+                if let Some(status) = e.status() {
+                    RawResponse::new(status).send(reader.get_mut()).await;
+                }
+                break;
+            }
+        };
+        let response = intercepted_response(request, host_name, state).await;
+        if !response.send(reader.get_mut()).await || !response.is_keep_alive() {
+            break;
+        }
+    }
+    let _ = reader.get_mut().shutdown().await;
+}
+
+/// 拦截主机上的一个请求
+///
+/// 授权入口返回微信授权页，OAuth 回调捕获登录，这两种响应之后关闭连接；其余请求转发给上游，客户端要求时保持连接
+async fn intercepted_response(request: ParsedRequest, host_name: &str, state: &ProxyState) -> RawResponse {
+    let path = request.path.as_str();
+    let full_url = format!("https://{}{}", host_name, path);
+    // 回调地址的查询串里有 OAuth code，只记录路径
+    debug!(method = %request.method, path = path.split('?').next().unwrap_or_default(), "intercepted request");
+
+    if path == AUTHORIZE_PATH {
+        match get_open_url(&full_url).await {
+            Ok(location) => RawResponse::redirect(&location),
+            Err(e) => {
+                warn!(error = %e, "get_open_url failed");
+                RawResponse::error_page(StatusCode::BAD_GATEWAY, &format!("获取授权地址失败: {e}"))
+            }
+        }
+    } else if path.starts_with(CALLBACK_PATH) {
+        match capture_login(full_url, &request.headers, state).await {
+            Ok(token) => {
+                debug!("login captured, redirecting to frontend");
+                RawResponse::redirect(&state.config.redirect_url(&token))
+            }
+            Err(e) => {
+                warn!(error = ?e, "login capture failed");
+                RawResponse::error_page(StatusCode::BAD_GATEWAY, &format!("登录失败，请重新扫码: {e:#}"))
+            }
+        }
+    } else {
+        let keep_alive = request.keep_alive();
+        let headers = request.headers.iter().map(|(n, v)| (n.as_str(), v.as_bytes()));
+        match forward(&state.client, &request.method, &full_url, headers, request.body).await {
+            Ok(res) => match upstream_response(res).await {
+                Ok(response) => response.keep_alive(keep_alive),
+                Err(e) => {
+                    warn!(error = %e, "read upstream response failed");
+                    RawResponse::error_page(StatusCode::BAD_GATEWAY, "读取上游响应失败")
+                }
+            },
+            Err(e) => {
+                warn!(error = %e, "forward failed");
+                RawResponse::error_page(StatusCode::BAD_GATEWAY, "连接上游失败")
+            }
+        }
+    }
+}
+
+/// 用回调地址换取登录信息并创建会话，返回会话令牌
+async fn capture_login(full_url: String, headers: &[(String, String)], state: &ProxyState) -> Result<String> {
+    let (login, open_user_id, cookies) = maimai_handle(full_url, headers).await?;
+    let session = Session::new(login, open_user_id, cookies, state.config.session_ttl);
+    state.sessions.create(&session).await
+}
+
+/// 上游响应体已完整读出，统一改用 `Content-Length`
+async fn upstream_response(res: reqwest::Response) -> Result<RawResponse> {
+    let status = StatusCode::from_u16(res.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut response = RawResponse::new(status);
+    for (name, value) in res.headers().iter() {
+        if is_hop_by_hop(name.as_str()) {
+            continue;
+        }
+        response = response.header(name.as_str(), &String::from_utf8_lossy(value.as_bytes()));
+    }
+    Ok(response.raw_body(res.bytes().await?.to_vec()))
+}
+
+
+/// 启动代理，捕获到的登录保存在 `sessions` 中
+pub async fn service(config: Arc<ProxyConfig>, sessions: Arc<dyn SessionStore>) {
+    let addr = config.listen;
+    let state = match ProxyState::new(config, sessions) {
+        Ok(state) => Arc::new(state),
+        Err(e) => {
+            error!(error = ?e, "代理初始化失败（本地 CA 加载失败？），代理未启动");
+            return;
+        }
+    };
+    let make_svc = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle_proxy_request(req, state.clone())))
+        }
     });
     let server = Server::bind(&addr).serve(make_svc);
-    println!("代理监听在 http://{}", addr);
+    info!("代理监听在 http://{}", addr);
     if let Err(e) = server.await {
-        eprintln!("服务器错误: {}", e);
+        error!(error = %e, "服务器错误");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use crate::session::MemorySessionStore;
+
+    fn test_config() -> ProxyConfig {
+        let certs = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../certs");
+        ProxyConfig {
+            ca_cert_path: certs.join("local-ca-cert.pem"),
+            ca_key_path: certs.join("local-ca-key.pem"),
+            ..Default::default()
+        }
+    }
+
+    async fn start_proxy(config: ProxyConfig) -> SocketAddr {
+        start_proxy_with(config, true).await
+    }
+
+    /// `check_targets` 为 false 时允许直通到本机的测试服务器
+    async fn start_proxy_with(config: ProxyConfig, check_targets: bool) -> SocketAddr {
+        let mut state = ProxyState::new(Arc::new(config), Arc::new(MemorySessionStore::new())).unwrap();
+        state.check_targets = check_targets;
+        let state = Arc::new(state);
+        let make_svc = make_service_fn(move |_conn| {
+            let state = state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle_proxy_request(req, state.clone()))) }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    /// 原样回显收到的字节
+    async fn start_echo() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut r, mut w) = stream.split();
+                    let _ = tokio::io::copy(&mut r, &mut w).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn send(proxy: SocketAddr, request: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        (stream, String::from_utf8_lossy(&buf[..n]).into_owned())
+    }
+
+    /// 回显路径和 `Via` 头的 HTTP 服务器
+    async fn start_upstream() -> SocketAddr {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let via = req.headers().get(header::VIA).and_then(|v| v.to_str().ok()).unwrap_or_default();
+                Ok::<_, Infallible>(Response::new(Body::from(format!("upstream {} via {via}", req.uri().path()))))
+            }))
+        });
+        let upstream = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = upstream.local_addr();
+        tokio::spawn(upstream);
+        addr
+    }
+
+    fn passthrough_config() -> ProxyConfig {
+        ProxyConfig { passthrough: true, ..test_config() }
+    }
+
+    #[tokio::test]
+    async fn connect_to_other_hosts_is_spliced() {
+        let echo = start_echo().await;
+        let proxy = start_proxy_with(passthrough_config(), false).await;
+        let (mut stream, head) = send(proxy, &format!("CONNECT {echo} HTTP/1.1\r\nHost: {echo}\r\n\r\n")).await;
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+
+        // 不拦截的主机不会被终止 TLS，字节原样往返
+        let hello = b"\x16\x03\x01 client hello";
+        stream.write_all(hello).await.unwrap();
+        let mut buf = vec![0u8; hello.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, hello);
+    }
+
+    #[tokio::test]
+    async fn plain_http_to_other_hosts_is_forwarded() {
+        let upstream_addr = start_upstream().await;
+        let proxy = start_proxy_with(passthrough_config(), false).await;
+
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::http(format!("http://{proxy}")).unwrap())
+            .build()
+            .unwrap();
+        let body = client.get(format!("http://{upstream_addr}/hello")).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "upstream /hello via 1.1 maida-proxy");
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_is_502() {
+        // 先占一个端口再释放，之后连接会被拒绝
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let proxy = start_proxy_with(passthrough_config(), false).await;
+        let (_, head) = send(proxy, &format!("GET http://{closed}/ HTTP/1.1\r\nHost: {closed}\r\n\r\n")).await;
+        assert!(head.starts_with("HTTP/1.1 502"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn strict_mode_blocks_other_hosts() {
+        let echo = start_echo().await;
+        let proxy = start_proxy(test_config()).await;
+        let (_, head) = send(proxy, &format!("CONNECT {echo} HTTP/1.1\r\nHost: {echo}\r\n\r\n")).await;
+        assert!(head.starts_with("HTTP/1.1 403"), "{head}");
+        let (_, head) = send(proxy, &format!("GET http://{echo}/ HTTP/1.1\r\nHost: {echo}\r\n\r\n")).await;
+        assert!(head.starts_with("HTTP/1.1 403"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn passthrough_refuses_private_addresses_and_other_ports() {
+        let echo = start_echo().await;
+        let proxy = start_proxy(passthrough_config()).await;
+        for target in [echo.to_string(), "127.0.0.1:443".to_string(), "example.com:22".to_string()] {
+            let (_, head) = send(proxy, &format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n")).await;
+            assert!(head.starts_with("HTTP/1.1 403"), "{target}: {head}");
+        }
+        let (_, head) = send(proxy, "GET http://192.168.1.1/ HTTP/1.1\r\nHost: 192.168.1.1\r\n\r\n").await;
+        assert!(head.starts_with("HTTP/1.1 403"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn setup_check_reaches_local_api_without_passthrough() {
+        let api = start_upstream().await;
+        let proxy = start_proxy(ProxyConfig { api_listen: api, ..test_config() }).await;
+        let check = format!("192.168.10.9:{}", api.port());
+        let (_, head) = send(proxy, &format!("GET http://{check}{SETUP_CHECK_PATH} HTTP/1.1\r\nHost: {check}\r\n\r\n")).await;
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+        assert!(head.ends_with("upstream /setup/check via 1.1 maida-proxy"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn plain_http_to_intercepted_host_is_captured() {
+        // 本机 443 端口没有服务，捕获会失败，但走的是捕获流程而不是普通转发
+        let config = ProxyConfig { intercept_hosts: vec!["localhost".to_string()], ..test_config() };
+        let proxy = start_proxy(config).await;
+        let request = format!("GET http://localhost:8080{CALLBACK_PATH}?r=1&code=x HTTP/1.1\r\nHost: localhost:8080\r\n\r\n");
+        let (_, head) = send(proxy, &request).await;
+        assert!(head.starts_with("HTTP/1.1 502"), "{head}");
+        assert!(head.contains("登录失败"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn ipv6_connect_target_is_parsed() {
+        let proxy = start_proxy(ProxyConfig { passthrough: true, ..test_config() }).await;
+        let (_, head) = send(proxy, "CONNECT [::1]:443 HTTP/1.1\r\nHost: [::1]:443\r\n\r\n").await;
+        assert!(head.starts_with("HTTP/1.1 403"), "{head}");
+    }
+
+    #[test]
+    fn public_ip_check() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:192.168.1.1"] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["1.1.1.1", "101.226.1.1", "2400:3200::1"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
     }
 }
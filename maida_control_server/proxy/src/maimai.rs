@@ -1,13 +1,9 @@
 use std::collections::HashMap;
-use std::io::ErrorKind::NotFound;
-use httparse::Header;
 use reqwest::redirect::Policy;
-use reqwest::Url;
 use anyhow::{anyhow, Error, Result};
+use tracing::{debug, instrument};
 
-use serde::Deserialize;
-
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LoginResponse {
     #[serde(rename = "errorID")]
     pub error_id: u32,
@@ -32,22 +28,21 @@ pub struct LoginResponse {
 }
 
 ///open.weixin.qq.com
+#[instrument(skip_all)]
 pub async fn get_open_url(url:&String)->Result<String>{
     let client = reqwest::Client::builder()
         .redirect(Policy::none()) // <-- 关键！禁止自动重定向
         .build()?;
     let method = reqwest::Method::GET;
 
-    let mut req_builder = client.request(method, url);
+    let req_builder = client.request(method, url);
     match req_builder.send().await {
         Ok(res)=>{
-            println!("\n✅ 成功从目标服务器获取响应 ✅");
-            println!("<-- 响应状态: {}", res.status());
+            debug!(status = %res.status(), "成功从目标服务器获取响应");
             let location = res.headers().get("location");
             if let Some(_location)=location{
                 let _location = _location.to_str()?.to_string();
-                let _location = _location.replace("https%3A%2F%2Ftgk-wcaime.wahlap.com","http%3A%2F%2Ftgk-wcaime.wahlap.com");
-                println!("location:{}",_location);
+                debug!("authorize redirect");
 
                 Ok(_location)
             }else {
@@ -60,10 +55,11 @@ pub async fn get_open_url(url:&String)->Result<String>{
 }
 
 ///URL tgk-wcaime.wahlap.com/wc_auth/oauth/callback/maimai-dx?r=___&t=___&code=___&state=___
-pub async fn maimai_handle<'headers, 'buf>(full_url:String, headers: &'headers mut [Header<'buf>])->Result<(LoginResponse,String)>{
+#[instrument(skip_all)]
+pub async fn maimai_handle(full_url:String, headers: &[(String, String)])->Result<(LoginResponse,String,HashMap<String, String>)>{
 
-        println!("\n✅ 成功捕获请求，准备使用 reqwest 转发 ✅");
-        println!("URL: {}", full_url);
+        // URL 中带有 OAuth code，不记录
+        debug!("成功捕获请求，准备使用 reqwest 转发");
 
         let client = reqwest::Client::builder()
             .redirect(Policy::none()) // <-- 关键！禁止自动重定向
@@ -71,27 +67,26 @@ pub async fn maimai_handle<'headers, 'buf>(full_url:String, headers: &'headers m
         let method = reqwest::Method::GET;
 
         let mut req_builder = client.request(method, &full_url);
-        println!("--> 正在转发其余 headers:");
-        for header in headers.iter() {
-            if !header.name.eq_ignore_ascii_case("Host") && !header.name.eq_ignore_ascii_case("Proxy-Connection") {
-                println!("    {}: {}", header.name, std::str::from_utf8(header.value).unwrap_or("<invalid utf8>"));
-                req_builder = req_builder.header(header.name, header.value);
+        for (name, value) in headers.iter() {
+            if !name.eq_ignore_ascii_case("Host") && !name.eq_ignore_ascii_case("Proxy-Connection") {
+                // 只记录 header 名，值里可能有 cookie
+                debug!(%name, "forward header");
+                req_builder = req_builder.header(name, value);
             }
         }
 
         match req_builder.send().await {
             Ok(response) => {
-                println!("\n✅ 成功从目标服务器获取data响应 ✅");
-                println!("URL: {}", full_url);
-                println!("<-- 响应状态: {}", response.status());
+                debug!(status = %response.status(), "成功从目标服务器获取data响应");
 
                 let location = response.headers().get("location");
                 if let Some(_location) = location {
-                    println!("location:{}", _location.to_str()?.to_string());
+                    // 跳转地址中带有 `t` 令牌，不记录
+                    debug!("callback redirect");
                     let (res,cookies) = get_user_data_handle(_location.to_str()?.to_string()).await?;
-                    let open_user_id = cookies.get("userId").unwrap().to_string();
+                    let open_user_id = cookies.get("userId").ok_or_else(|| anyhow!("响应中没有 userId cookie"))?.to_string();
 
-                    Ok((res,open_user_id))
+                    Ok((res,open_user_id,cookies))
                 } else {
                     Err(anyhow!("在响应中未找到 Location 头部"))
                 }
@@ -103,42 +98,66 @@ pub async fn maimai_handle<'headers, 'buf>(full_url:String, headers: &'headers m
 }
 
 ///URL maimai.wahlap.com/maimai-mobile/?t=___
+#[instrument(skip_all)]
 pub async fn get_user_data_handle(url:String)-> Result<(LoginResponse,HashMap<String, String>)>{
     let client = reqwest::Client::builder()
         .redirect(Policy::none()) // <-- 关键！禁止自动重定向
         .build()?;
     let method = reqwest::Method::GET;
 
-    let mut req_builder = client.request(method, &url);
+    let req_builder = client.request(method, &url);
     match req_builder.send().await {
         Ok(res)=>{
-            println!("\n✅ 成功从目标服务器获取响应 ✅");
-            println!("<-- 响应状态: {}", res.status());
+            debug!(status = %res.status(), "成功从目标服务器获取响应");
 
             let mut cookies = HashMap::new();
             for val in res.headers().get_all("set-cookie").iter() {
-                if let Ok(s) = val.to_str() {
-                    if let Some((k, v)) = s.split_once('=') {
-                        // cookie 只取到第一个分号前
-                        let v = v.split(';').next().unwrap_or("").to_string();
-                        cookies.insert(k.trim().to_string(), v);
-                    }
+                if let Ok(s) = val.to_str()
+                    && let Some((k, v)) = s.split_once('=')
+                {
+                    // cookie 只取到第一个分号前
+                    let v = v.split(';').next().unwrap_or("").to_string();
+                    cookies.insert(k.trim().to_string(), v);
                 }
             }
 
 
             let text = res.text().await?;
-            let text = {let texts:Vec<&str>=text.split("login=").collect();texts[1]};
-            let json_part = text
-                .trim_end()                // 去掉 \n \r 空格
-                .strip_suffix('"')         // 去掉最后一个 "
-                .unwrap_or(text)
-                .trim();                   // 再保险修剪一次
-
-            println!("json_part:{json_part}");
-            let parsed: LoginResponse = serde_json::from_str(&json_part)?;
+            let parsed = parse_login_page(&text)?;
+            // 只记录非敏感字段，sessionId 不能出现在日志里
+            debug!(error_id = parsed.error_id, "login json");
             Ok((parsed,cookies))
         },
         Err(e) => Err(Error::from(e)),
     }
 }
+
+/// 页面中 `login=` 之后是登录结果的 JSON，以 `"` 结尾
+fn parse_login_page(text: &str) -> Result<LoginResponse> {
+    let (_, text) = text.split_once("login=").ok_or_else(|| anyhow!("页面中没有登录信息，可能需要重新扫码"))?;
+    let json_part = text
+        .trim_end()                // 去掉 \n \r 空格
+        .strip_suffix('"')         // 去掉最后一个 "
+        .unwrap_or(text)
+        .trim();                   // 再保险修剪一次
+    Ok(serde_json::from_str(json_part)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_page_is_parsed() {
+        let page = r#"<a href="?login={"errorID":0,"openGameID":"MAID","userID":12771153,"sessionId":1,"userPlayFlag":true,"newUserIdFlag":false,"openGameIDFlag":true}"
+"#;
+        let login = parse_login_page(page).unwrap();
+        assert_eq!((login.error_id, login.user_id), (0, 12771153));
+    }
+
+    #[test]
+    fn page_without_login_is_an_error() {
+        assert!(parse_login_page("<html>error</html>").is_err());
+        assert!(parse_login_page("login={").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! 代理自动配置（PAC）文件
+//!
+//! 手机 Wi-Fi 代理设为“自动”并填入 PAC 地址后，只有拦截的登录主机和自检地址经过代理，其他流量直连
+
+use crate::config::ProxyConfig;
+
+pub const PAC_MIME: &str = "application/x-ns-proxy-autoconfig";
+
+/// `proxy_addr` 为手机访问代理用的 `host:port`，`check_url` 为自检地址，也经过代理
+pub fn generate(config: &ProxyConfig, proxy_addr: &str, check_url: &str) -> String {
+    let hosts = config
+        .intercept_hosts
+        .iter()
+        .map(|h| format!("host == \"{}\"", js_escape(&h.to_ascii_lowercase())))
+        .collect::<Vec<_>>()
+        .join(" || ");
+    format!(
+        r#"function FindProxyForURL(url, host) {{
+    host = host.toLowerCase();
+    if ({hosts}) {{
+        return "PROXY {proxy}";
+    }}
+    if (url.indexOf("{check}") == 0) {{
+        return "PROXY {proxy}";
+    }}
+    return "DIRECT";
+}}
+"#,
+        proxy = js_escape(proxy_addr),
+        check = js_escape(check_url),
+    )
+}
+
+fn js_escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control())
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_only_intercepted_hosts_and_check() {
+        let config = ProxyConfig {
+            intercept_hosts: vec!["tgk-wcaime.wahlap.com".to_string(), "Example.wahlap.com".to_string()],
+            ..Default::default()
+        };
+        let pac = generate(&config, "192.168.10.9:9854", "http://192.168.10.9:9855/setup/check");
+        assert_eq!(
+            pac,
+            r#"function FindProxyForURL(url, host) {
+    host = host.toLowerCase();
+    if (host == "tgk-wcaime.wahlap.com" || host == "example.wahlap.com") {
+        return "PROXY 192.168.10.9:9854";
+    }
+    if (url.indexOf("http://192.168.10.9:9855/setup/check") == 0) {
+        return "PROXY 192.168.10.9:9854";
+    }
+    return "DIRECT";
+}
+"#
+        );
+    }
+
+    #[test]
+    fn host_header_cannot_break_out_of_strings() {
+        let pac = generate(&ProxyConfig::default(), "evil\"); alert(1); (\"", "http://x/setup/check");
+        assert!(pac.contains(r#"PROXY evil\"); alert(1); (\""#));
+    }
+}
@@ -0,0 +1,168 @@
+//! 隧道内写回浏览器的 HTTP/1.1 响应
+//!
+//! 拦截的连接不经过 hyper，响应由 [`RawResponse`] 拼出状态行、头部和响应体，
+//! 总是带 `Content-Length` 和 `Connection`。写入失败只记录日志，不会让任务 panic
+
+use hyper::http::{HeaderName, HeaderValue};
+use hyper::{Body, Response, StatusCode};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+impl RawResponse {
+    /// 默认不保持连接
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            keep_alive: false,
+        }
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self::new(StatusCode::FOUND).header("Location", location)
+    }
+
+    /// 带说明的 HTML 错误页，`message` 会被转义
+    pub fn error_page(status: StatusCode, message: &str) -> Self {
+        let body = format!(
+            r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head><meta charset="UTF-8"><title>MaiDaControl</title></head>
+<body><h1>MaiDaControl 代理错误</h1><p>{}</p></body>
+</html>
+"#,
+            html_escape(message)
+        );
+        Self::new(status).body("text/html; charset=utf-8", body.into_bytes())
+    }
+
+    /// 头部值中的 CR / LF 会被去掉，防止响应头注入
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let value: String = value.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+        self.headers.push((name.to_string(), value));
+        self
+    }
+
+    pub fn body(self, content_type: &str, body: Vec<u8>) -> Self {
+        let mut response = self.header("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    /// 不改动已有的 `Content-Type`，用于转发上游响应
+    pub fn raw_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// 转换为 hyper 的响应，用于普通 HTTP 代理请求，连接由 hyper 管理，无效的头部会被丢弃
+    pub fn into_response(self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        response
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status.as_u16(),
+            self.status.canonical_reason().unwrap_or("Unknown")
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        head.push_str(if self.keep_alive { "Connection: keep-alive\r\n\r\n" } else { "Connection: close\r\n\r\n" });
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> std::io::Result<()> {
+        stream.write_all(&self.to_bytes()).await?;
+        stream.flush().await
+    }
+
+    /// 写入响应，失败时记录日志并返回 `false`（通常是浏览器已断开）
+    pub async fn send<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> bool {
+        match self.write_to(stream).await {
+            Ok(()) => true,
+            Err(e) => {
+                debug!(status = %self.status, error = %e, "write response failed");
+                false
+            }
+        }
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_layout() {
+        let bytes = RawResponse::redirect("https://open.weixin.qq.com/connect?x=1").to_bytes();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 302 Found\r\n\
+             Location: https://open.weixin.qq.com/connect?x=1\r\n\
+             Content-Length: 0\r\n\
+             Connection: close\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn error_page_is_escaped_and_sized() {
+        let response = RawResponse::error_page(StatusCode::BAD_GATEWAY, "<script>x</script>").keep_alive(true);
+        let text = String::from_utf8(response.to_bytes()).unwrap();
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+        assert!(head.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(head.ends_with("Connection: keep-alive"));
+        assert!(body.contains("&lt;script&gt;x&lt;/script&gt;"));
+    }
+
+    #[test]
+    fn header_injection_is_stripped() {
+        let text = String::from_utf8(RawResponse::redirect("/a\r\nSet-Cookie: x=1").to_bytes()).unwrap();
+        assert!(text.contains("Location: /aSet-Cookie: x=1\r\n"));
+        assert!(!text.contains("\r\nSet-Cookie"));
+    }
+
+    #[tokio::test]
+    async fn closed_stream_is_not_a_panic() {
+        let (client, server) = tokio::io::duplex(64);
+        drop(client);
+        let mut server = server;
+        assert!(!RawResponse::new(StatusCode::OK).send(&mut server).await);
+    }
+}
@@ -0,0 +1,244 @@
+//! 服务端会话
+//!
+//! 代理捕获到 OAuth 回调后，把 [`LoginResponse`] 和舞萌 DX 网页的 cookie 存在服务端，
+//! 只把随机生成的不透明令牌交给浏览器。游戏的 `sessionId`、`_t` cookie 不会出现在 URL、
+//! 浏览器历史或 Referer 中。
+//!
+//! 存储实现了 [`SessionStore`]：默认 [`MemorySessionStore`]，开启 `sqlite` feature 后可用 `SqliteSessionStore`
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use crate::maimai::LoginResponse;
+
+/// 会话默认有效期
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub login: LoginResponse,
+    /// 舞萌 DX 网页 cookie 中的 `userId`
+    pub open_user_id: String,
+    /// 舞萌 DX 网页的 cookie（含 `_t`）
+    pub cookies: HashMap<String, String>,
+    /// 过期时间（unix 秒）
+    pub expires_at: u64,
+}
+
+impl Session {
+    pub fn new(login: LoginResponse, open_user_id: String, cookies: HashMap<String, String>, ttl: Duration) -> Self {
+        Self {
+            login,
+            open_user_id,
+            cookies,
+            expires_at: now_secs() + ttl.as_secs(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= now_secs()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// 生成 32 字节随机数的十六进制令牌
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// 新建或覆盖令牌对应的会话
+    async fn put(&self, token: &str, session: &Session) -> Result<()>;
+
+    /// 取出未过期的会话
+    async fn get(&self, token: &str) -> Result<Option<Session>>;
+
+    async fn remove(&self, token: &str) -> Result<()>;
+
+    /// 删除所有已过期的会话，返回删除的数量
+    async fn purge_expired(&self) -> Result<u64>;
+
+    /// 为新会话生成令牌并保存
+    async fn create(&self, session: &Session) -> Result<String> {
+        let token = new_token();
+        self.put(&token, session).await?;
+        Ok(token)
+    }
+}
+
+/// 进程内存储，重启后会话全部失效
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn put(&self, token: &str, session: &Session) -> Result<()> {
+        self.sessions.write().await.insert(token.to_string(), session.clone());
+        Ok(())
+    }
+
+    async fn get(&self, token: &str) -> Result<Option<Session>> {
+        Ok(self.sessions.read().await.get(token).filter(|s| !s.is_expired()).cloned())
+    }
+
+    async fn remove(&self, token: &str) -> Result<()> {
+        self.sessions.write().await.remove(token);
+        Ok(())
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, s| !s.is_expired());
+        Ok((before - sessions.len()) as u64)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteSessionStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+    use std::str::FromStr;
+
+    /// SQLite 存储，会话以 JSON 保存在 `sessions` 表中
+    pub struct SqliteSessionStore {
+        pool: SqlitePool,
+    }
+
+    impl SqliteSessionStore {
+        /// `url` 形如 `sqlite://sessions.db`，文件不存在时自动创建
+        pub async fn connect(url: &str) -> Result<Self> {
+            let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+            Self::with_pool(SqlitePool::connect_with(options).await?).await
+        }
+
+        pub async fn with_pool(pool: SqlitePool) -> Result<Self> {
+            sqlx::query(
+                "CREATE TABLE IF NOT EXISTS sessions (
+                    token TEXT PRIMARY KEY,
+                    data TEXT NOT NULL,
+                    expires_at INTEGER NOT NULL
+                )",
+            )
+            .execute(&pool)
+            .await?;
+            Ok(Self { pool })
+        }
+    }
+
+    #[async_trait]
+    impl SessionStore for SqliteSessionStore {
+        async fn put(&self, token: &str, session: &Session) -> Result<()> {
+            sqlx::query("INSERT OR REPLACE INTO sessions (token, data, expires_at) VALUES (?, ?, ?)")
+                .bind(token)
+                .bind(serde_json::to_string(session)?)
+                .bind(session.expires_at as i64)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        }
+
+        async fn get(&self, token: &str) -> Result<Option<Session>> {
+            let data: Option<String> = sqlx::query_scalar("SELECT data FROM sessions WHERE token = ? AND expires_at > ?")
+                .bind(token)
+                .bind(now_secs() as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+            Ok(data.map(|d| serde_json::from_str(&d)).transpose()?)
+        }
+
+        async fn remove(&self, token: &str) -> Result<()> {
+            sqlx::query("DELETE FROM sessions WHERE token = ?")
+                .bind(token)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        }
+
+        async fn purge_expired(&self) -> Result<u64> {
+            let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+                .bind(now_secs() as i64)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(ttl: Duration) -> Session {
+        let login = LoginResponse {
+            error_id: 0,
+            open_game_id: "MAID".to_string(),
+            user_id: 12771153,
+            session_id: 998877,
+            user_play_flag: true,
+            new_user_id_flag: false,
+            open_game_id_flag: true,
+        };
+        let cookies = HashMap::from([("_t".to_string(), "abc".to_string())]);
+        Session::new(login, "open-id".to_string(), cookies, ttl)
+    }
+
+    async fn round_trip(store: &dyn SessionStore) {
+        let token = store.create(&session(DEFAULT_SESSION_TTL)).await.unwrap();
+        assert_eq!(token.len(), 64);
+        let found = store.get(&token).await.unwrap().unwrap();
+        assert_eq!(found.login.user_id, 12771153);
+        assert_eq!(found.cookies["_t"], "abc");
+
+        store.remove(&token).await.unwrap();
+        assert!(store.get(&token).await.unwrap().is_none());
+
+        let expired = store.create(&session(Duration::ZERO)).await.unwrap();
+        assert!(store.get(&expired).await.unwrap().is_none());
+        assert_eq!(store.purge_expired().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn memory_store() {
+        round_trip(&MemorySessionStore::new()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store() {
+        // 内存数据库每个连接各自独立，只能用单连接
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let store = SqliteSessionStore::with_pool(pool).await.unwrap();
+        round_trip(&store).await;
+    }
+
+    #[test]
+    fn tokens_are_unique() {
+        assert_ne!(new_token(), new_token());
+    }
+}
@@ -0,0 +1,163 @@
+//! 中间人 TLS
+//!
+//! 对需要拦截的主机，代理用配置的本地 CA（`certs/local-ca-*.pem`）即时签发该主机的证书并终止 TLS，
+//! 手机安装并信任该 CA 后即可看到明文请求。签发过的证书按主机名缓存
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Context, Result};
+use rcgen::{
+    Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, KeyPair, KeyUsagePurpose,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use time::{Duration, OffsetDateTime};
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+/// 签发的证书有效期，iOS 不接受超过 825 天的服务器证书
+const LEAF_VALIDITY: Duration = Duration::days(365);
+
+pub struct CertAuthority {
+    cert: Certificate,
+    key: KeyPair,
+    cache: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+impl CertAuthority {
+    pub fn load(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        let cert_pem = std::fs::read_to_string(cert_path).with_context(|| format!("读取 CA 证书 {} 失败", cert_path.display()))?;
+        let key_pem = std::fs::read_to_string(key_path).with_context(|| format!("读取 CA 私钥 {} 失败", key_path.display()))?;
+        Self::from_pem(&cert_pem, &key_pem)
+    }
+
+    pub fn from_pem(cert_pem: &str, key_pem: &str) -> Result<Self> {
+        let key = KeyPair::from_pem(key_pem).map_err(|e| anyhow!("CA 私钥无效: {e}"))?;
+        let params = CertificateParams::from_ca_cert_pem(cert_pem).map_err(|e| anyhow!("CA 证书无效: {e}"))?;
+        // 用原证书的名称和私钥重新构造签发者，签发出的证书能由手机上安装的原 CA 验证
+        let cert = params.self_signed(&key).map_err(|e| anyhow!("CA 证书无效: {e}"))?;
+        Ok(Self {
+            cert,
+            key,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 为 `host` 签发证书，返回只含该证书的 TLS 配置
+    pub fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>> {
+        let host = host.to_ascii_lowercase();
+        if let Some(config) = self.cache.lock().unwrap().get(&host) {
+            return Ok(config.clone());
+        }
+        let config = Arc::new(self.issue(&host)?);
+        debug!(%host, "issued leaf certificate");
+        self.cache.lock().unwrap().insert(host, config.clone());
+        Ok(config)
+    }
+
+    pub fn acceptor(&self, host: &str) -> Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(self.server_config(host)?))
+    }
+
+    fn issue(&self, host: &str) -> Result<ServerConfig> {
+        let mut params = CertificateParams::new(vec![host.to_string()])?;
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, host);
+        params.distinguished_name = name;
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + LEAF_VALIDITY;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+
+        let key = KeyPair::generate()?;
+        let cert = params.signed_by(&key, &self.cert, &self.key)?;
+        let chain = vec![cert.der().clone(), CertificateDer::from(self.cert.der().to_vec())];
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+/// 读取 PEM 格式的 CA 证书并转为 DER，供手机下载安装
+pub fn load_ca_der(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).with_context(|| format!("读取 CA 证书 {} 失败", path.display()))?;
+    let pem = pem::parse(text).map_err(|e| anyhow!("CA 证书无效: {e}"))?;
+    Ok(pem.into_contents())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    fn test_ca() -> (CertAuthority, CertificateDer<'static>) {
+        let mut params = CertificateParams::default();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "Test CA");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        let ca = CertAuthority::from_pem(&cert.pem(), &key.serialize_pem()).unwrap();
+        (ca, cert.der().clone())
+    }
+
+    #[tokio::test]
+    async fn leaf_is_trusted_by_ca() {
+        let (ca, root) = test_ca();
+        let acceptor = ca.acceptor("tgk-wcaime.wahlap.com").unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(root).unwrap();
+        let client = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client));
+
+        let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server_io).await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        let name = ServerName::try_from("tgk-wcaime.wahlap.com").unwrap();
+        let mut stream = connector.connect(name, client_io).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn configs_are_cached_per_host() {
+        let (ca, _) = test_ca();
+        let a = ca.server_config("tgk-wcaime.wahlap.com").unwrap();
+        let b = ca.server_config("TGK-WCAIME.wahlap.com").unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &ca.server_config("example.com").unwrap()));
+    }
+
+    #[test]
+    fn shipped_ca_can_sign() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../certs");
+        let ca = CertAuthority::load(dir.join("local-ca-cert.pem"), dir.join("local-ca-key.pem")).unwrap();
+        ca.server_config("tgk-wcaime.wahlap.com").unwrap();
+        // DER 以 SEQUENCE 开头
+        assert_eq!(load_ca_der(dir.join("local-ca-cert.pem")).unwrap()[0], 0x30);
+    }
+}
@@ -0,0 +1,88 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use mai_api::TitleServerError;
+use serde_json::json;
+use crate::mobile_handle::session::MobileError;
+
+/// REST 接口的错误，统一返回 `{"error": 错误码, "message": 说明}`
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("接口不存在")]
+    NotFound,
+    #[error(transparent)]
+    TitleServer(#[from] TitleServerError),
+    #[error(transparent)]
+    Mobile(#[from] MobileError),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::NotFound => "not_found",
+            ApiError::TitleServer(_) => "title_server",
+            ApiError::Mobile(MobileError::SessionExpired) => "session_expired",
+            ApiError::Mobile(_) => "mobile",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::TitleServer(e) => match e {
+                TitleServerError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                TitleServerError::Http { status, .. } if status.as_u16() == 503 => StatusCode::SERVICE_UNAVAILABLE,
+                TitleServerError::Build(_) | TitleServerError::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_GATEWAY,
+            },
+            ApiError::Mobile(e) => match e {
+                MobileError::SessionExpired => StatusCode::UNAUTHORIZED,
+                MobileError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_GATEWAY,
+            },
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// 辅助函数大多返回 `anyhow::Result`，能还原出标题服务器或网页错误时保留原始错误
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<TitleServerError>() {
+            Ok(e) => return ApiError::TitleServer(e),
+            Err(e) => e,
+        };
+        match e.downcast::<MobileError>() {
+            Ok(e) => ApiError::Mobile(e),
+            Err(e) => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        // `retryable` 告诉前端是否值得稍后重试
+        if let ApiError::TitleServer(e) = &self {
+            body["retryable"] = json!(e.is_retryable());
+            if let TitleServerError::ServerErrorId(id) = e {
+                body["errorId"] = json!(id);
+            }
+        }
+        (self.status(), Json(body)).into_response()
+    }
+}
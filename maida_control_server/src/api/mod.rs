@@ -0,0 +1,188 @@
+//! 对前端提供的 REST 接口
+//!
+//! | 路由 | 返回 |
+//! |---|---|
+//! | `GET /v1/me/preview` | 标题服务器 `GetUserPreviewApi`（[`UserPreview`]） |
+//! | `GET /v1/me/records` | 所有游玩过的谱面成绩（[`UserMusicDetail`] 数组） |
+//! | `GET /v1/me/records/recent` | 网页上最近的游玩记录（[`PlayRecord`] 数组） |
+//! | `GET /v1/me/records/genre?genre=..&diff=..` | 网页上某个分类某个难度的最好成绩 |
+//! | `GET /v1/me/records/detail?idx=..` | 网页上单曲各难度的最好成绩 |
+//! | `GET /v1/me/favorites` | 收藏的乐曲（[`FavoriteMusic`] 数组） |
+//! | `GET /v1/me/rating` | 标题服务器 `GetUserRatingApi`（[`UserRatingResponse`]） |
+//! | `GET /v1/me/rating/targets?gain=..&limit=..` | 总 rating 提升 `gain`（默认 1）所需的达成率（[`RatingTarget`] 数组） |
+//! | `DELETE /v1/session` | 注销当前会话 |
+//!
+//! 当前用户由 `Authorization: Bearer <令牌>` 确定，令牌由代理在登录后签发（见 [`proxy::session`]），
+//! 缺少、无效或已过期时返回 401。读取网页的接口在网页会话失效时也返回 401（`session_expired`），
+//! 需要重新登录。出错时返回对应的 HTTP 状态码和 [`ApiError`] 格式的 JSON
+
+pub mod error;
+
+use std::sync::Arc;
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use proxy::config::ProxyConfig;
+use proxy::session::{Session, SessionStore};
+use serde::Deserialize;
+use mai_api::helper_get_user_music_detail::get_user_full_music_detail;
+use mai_api::rating::{rating_targets, RatingTarget};
+use mai_api::{get_user_preview_api, get_user_rating_api, UserIdRequest, UserMusicDetail, UserPreview, UserRatingResponse};
+use tracing::instrument;
+use crate::mobile_handle::{get_favorites, get_music_detail_records, get_music_genre_records, get_records};
+use crate::mobile_handle::session::{MobileSession, SessionLocks};
+use crate::mobile_handle::vo::{FavoriteMusic, PlayRecord, UserData};
+pub use error::ApiError;
+
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub sessions: Arc<dyn SessionStore>,
+    pub proxy: Arc<ProxyConfig>,
+    /// 串行化同一令牌的网页请求，见 [`SessionLocks`]
+    pub locks: SessionLocks,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/v1/me/preview", get(preview))
+        .route("/v1/me/records", get(records))
+        .route("/v1/me/records/recent", get(recent_records))
+        .route("/v1/me/records/genre", get(genre_records))
+        .route("/v1/me/records/detail", get(detail_records))
+        .route("/v1/me/favorites", get(favorites))
+        .route("/v1/me/rating", get(rating))
+        .route("/v1/me/rating/targets", get(targets))
+        .route("/v1/session", delete(logout))
+}
+
+/// 当前登录的用户
+pub struct Me {
+    pub token: String,
+    pub user_id: i64,
+    pub user_data: UserData,
+    pub session: Session,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Me {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::Unauthorized("缺少 Authorization: Bearer 令牌".to_string()))?
+            .trim()
+            .to_string();
+        let session = state
+            .sessions
+            .get(&token)
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized("会话不存在或已过期".to_string()))?;
+        let user_id = session.login.user_id as i64;
+        let user_data = UserData { user_id: user_id.to_string() };
+        Ok(Me { token, user_id, user_data, session })
+    }
+}
+
+/// 当前用户的网页会话，轮换后的 `_t` 会写回会话存储
+#[async_trait]
+impl FromRequestParts<AppState> for MobileSession {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let me = Me::from_request_parts(parts, state).await?;
+        Ok(MobileSession::new(state.sessions.clone(), state.locks.clone(), me.token, me.session)?)
+    }
+}
+
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&me.user_data.user_id)))]
+async fn preview(me: Me) -> ApiResult<UserPreview> {
+    let preview = get_user_preview_api(&UserIdRequest::new(me.user_id), me.user_data.user_id).await?;
+    Ok(Json(preview))
+}
+
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&me.user_data.user_id)))]
+async fn records(me: Me) -> ApiResult<Vec<UserMusicDetail>> {
+    let user_id = i32::try_from(me.user_id).map_err(|_| ApiError::BadRequest("会话中的 UID 超出范围".to_string()))?;
+    Ok(Json(get_user_full_music_detail(user_id).await?))
+}
+
+async fn recent_records(mut mobile: MobileSession) -> ApiResult<Vec<PlayRecord>> {
+    Ok(Json(get_records(&mut mobile).await?))
+}
+
+#[derive(Deserialize)]
+struct GenreQuery {
+    genre: u32,
+    diff: u32,
+}
+
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&mobile.user_data().user_id)))]
+async fn genre_records(mut mobile: MobileSession, Query(query): Query<GenreQuery>) -> ApiResult<Vec<PlayRecord>> {
+    Ok(Json(get_music_genre_records(&mut mobile, query.genre, query.diff).await?))
+}
+
+#[derive(Deserialize)]
+struct DetailQuery {
+    idx: String,
+}
+
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&mobile.user_data().user_id)))]
+async fn detail_records(mut mobile: MobileSession, Query(query): Query<DetailQuery>) -> ApiResult<Vec<PlayRecord>> {
+    Ok(Json(get_music_detail_records(&mut mobile, &query.idx).await?))
+}
+
+async fn favorites(mut mobile: MobileSession) -> ApiResult<Vec<FavoriteMusic>> {
+    Ok(Json(get_favorites(&mut mobile).await?))
+}
+
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&me.user_data.user_id)))]
+async fn rating(me: Me) -> ApiResult<UserRatingResponse> {
+    let rating = get_user_rating_api(&UserIdRequest::new(me.user_id), me.user_data.user_id).await?;
+    Ok(Json(rating))
+}
+
+#[derive(Deserialize)]
+struct TargetQuery {
+    #[serde(default = "default_gain")]
+    gain: i32,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_gain() -> i32 {
+    1
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&me.user_data.user_id)))]
+async fn targets(me: Me, Query(query): Query<TargetQuery>) -> ApiResult<Vec<RatingTarget>> {
+    if query.gain < 1 {
+        return Err(ApiError::BadRequest("gain 至少为 1".to_string()));
+    }
+    let user_id = i32::try_from(me.user_id).map_err(|_| ApiError::BadRequest("会话中的 UID 超出范围".to_string()))?;
+    let details = get_user_full_music_detail(user_id).await?;
+    let mut targets = rating_targets(&details, query.gain);
+    targets.truncate(query.limit);
+    Ok(Json(targets))
+}
+
+async fn logout(State(state): State<AppState>, me: Me) -> Result<StatusCode, ApiError> {
+    state.sessions.remove(&me.token).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn fallback() -> ApiError {
+    ApiError::NotFound
+}
@@ -1,64 +1,124 @@
+mod api;
 mod mobile_handle;
+mod setup;
 
 use axum::{
     routing::get,
     Router,
-    response::{IntoResponse, Redirect, Json},
-    extract::Query,
     http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
 };
-use tower_http::cors::{CorsLayer, Any};
+use axum::http::{header, HeaderValue};
+use tower_http::cors::{AllowOrigin, CorsLayer, Any};
 
-use serde::Serialize;
-use serde::Deserialize;
-use std::net::SocketAddr;
-use axum::http::{header, HeaderMap};
-use serde_json::{json, Value};
-use libs::mai_api::get_user_preview_api;
-use mobile_handle::vo;
-use crate::mobile_handle::get_records;
-use crate::mobile_handle::vo::UserData;
+use std::sync::Arc;
+use std::time::Duration;
+use mai_api::config::{self, Config};
+use tower_http::trace::TraceLayer;
+use tracing::{error, info, warn};
+use proxy::config::ProxyConfig;
+use proxy::session::{MemorySessionStore, SessionStore, SqliteSessionStore};
 
 const BASE_API: &str ="https://maimai.wahlap.com/maimai-mobile/";
 
 #[tokio::main]
 async fn main() {
-    tokio::spawn(proxy::service());
+    mai_api::init_logger();
+    match Config::load() {
+        Ok(c) => config::init(c),
+        Err(e) => {
+            error!("配置加载失败: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let proxy_config = match ProxyConfig::from_env() {
+        Ok(c) => Arc::new(c),
+        Err(e) => {
+            error!("代理配置无效: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // 启动时建表 / 执行迁移，数据库目前只由 mai_api 的成绩同步使用
+    if let Err(e) = mai_api::database::connect(&config::get().database_url).await {
+        error!("数据库初始化失败: {}", e);
+        std::process::exit(1);
+    }
+    let sessions = open_session_store().await;
+    tokio::spawn(purge_sessions(sessions.clone()));
+    tokio::spawn(proxy::service(proxy_config.clone(), sessions.clone()));
     // 路由配置
+    // 配置了前端来源时只允许该来源跨域访问，否则允许所有来源
+    let allow_origin = match proxy_config.frontend_origin.as_deref().map(HeaderValue::from_str) {
+        Some(Ok(origin)) => AllowOrigin::exact(origin),
+        Some(Err(e)) => {
+            error!("frontend_origin 无效: {}", e);
+            std::process::exit(1);
+        }
+        None => AllowOrigin::any(),
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any) // 允许所有来源
+        .allow_origin(allow_origin)
         .allow_methods(Any) // 允许所有方法 GET/POST/PUT...
-        .allow_headers(vec![
-            "*".parse().unwrap()
-        ]); // 允许自定义 headers
+        // 浏览器不会把 `*` 当作 Authorization，需要显式列出
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
     let app = Router::new()
         .route("/", get(root))
-        .route("/api", get(api))
         .route("/go", get(redirect_demo))
         .route("/oauth/authorize/maimai-dx", get(oauth_authorize))
-        .layer(cors);
+        .merge(api::router())
+        .merge(setup::router())
+        .fallback(api::fallback)
+        .with_state(api::AppState { sessions, proxy: proxy_config.clone(), locks: Default::default() })
+        .layer(cors)
+        .layer(TraceLayer::new_for_http());
 
     // 绑定地址
-    let addr = SocketAddr::from(([0, 0, 0, 0], 9855));
-    println!("服务启动在 http://{}", addr);
+    let addr = proxy_config.api_listen;
+    info!("服务启动在 http://{}", addr);
     axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app).await.unwrap();
 }
 
+/// 设置了 `MAI_SESSION_DB`（如 `sqlite://sessions.db`）时会话保存在 SQLite 中，否则只保存在内存里
+async fn open_session_store() -> Arc<dyn SessionStore> {
+    match std::env::var("MAI_SESSION_DB") {
+        Ok(url) => match SqliteSessionStore::connect(&url).await {
+            Ok(store) => Arc::new(store),
+            Err(e) => {
+                error!("会话数据库打开失败: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => Arc::new(MemorySessionStore::new()),
+    }
+}
+
+/// 定期清理过期会话
+async fn purge_sessions(sessions: Arc<dyn SessionStore>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+    loop {
+        interval.tick().await;
+        match sessions.purge_expired().await {
+            Ok(n) if n > 0 => info!(purged = n, "清理过期会话"),
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "清理过期会话失败"),
+        }
+    }
+}
+
 // 根路由
 async fn root() -> &'static str {
     "MaiDaControl is Working!"
 }
 
-// 返回 JSON
-#[derive(Serialize)]
-struct HelloResponse {
-    message: String,
-}
-
-async fn hello() -> impl IntoResponse {
-    Json(HelloResponse {
-        message: "Hello from Axum".to_string(),
-    })
+/// 跳转到微信授权页，授权后的回调经过代理被捕获，登录完成后跳回 [`ProxyConfig::redirect_base`]
+async fn oauth_authorize() -> Response {
+    match proxy::authorize_url().await {
+        Ok(location) => Redirect::temporary(&location).into_response(),
+        Err(e) => {
+            warn!(error = %e, "获取授权地址失败");
+            (StatusCode::BAD_GATEWAY, "获取授权地址失败，请稍后重试").into_response()
+        }
+    }
 }
 
 // 返回一个 302 重定向
@@ -67,65 +127,3 @@ async fn redirect_demo() -> Redirect {
 }
 
 
-async fn api(headers: HeaderMap) -> Json<Value> {
-    // 小工具：从 header 里安全取值
-    let get_header = |key: &str| -> Result<String, anyhow::Error> {
-        headers
-            .get(key)
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_owned())
-            .ok_or_else(|| anyhow::anyhow!("缺少 header: {}", key))
-    };
-
-    // 构建 UserData
-
-    let user_data = match (
-        get_header("X-User-Id"),
-        get_header("X-Open-User-Id"),
-        get_header("X-Session-Id"),
-    ) {
-        (Ok(user_id), Ok(open_user_id), Ok(session_id)) => UserData {
-            user_id,
-            open_user_id,
-            session_id,
-        },
-        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
-            return Json(json!({ "error": e.to_string() }));
-        }
-    };
-
-
-    let method = match get_header("method") {
-        Ok(m) => m,
-        Err(e) => {
-            println!("❌ 缺少 method: {:?}", e);
-            return Json(json!({ "error": e.to_string() }));
-        }
-    };
-
-    let data = json!({
-        "userId": user_data.user_id
-    });
-
-    match method.as_str() {
-        "Favorites" => {
-            // TODO: Favorites 逻辑
-            Json(json!({ "msg": "Favorites 未实现" }))
-        }
-        "OwnHomeData" => {
-            match get_user_preview_api(data, user_data.user_id.clone()).await {
-                Ok(res) => {
-                    println!("{:?}", res);
-                    get_records(user_data).await;
-                    // 这里直接转成 Json 返回
-                    Json(serde_json::from_str(&res).unwrap_or(json!({ "error": "解析失败" })))
-                }
-                Err(e) => {
-                    println!("❌ OwnHomeData 请求失败: {:?}", e);
-                    Json(json!({ "error": e.to_string() }))
-                }
-            }
-        }
-        _ => Json(json!({ "error": "未知 method" })),
-    }
-}
@@ -1,33 +1,145 @@
+pub mod record;
+pub mod session;
 pub mod vo;
 
-use reqwest::header::COOKIE;
-use reqwest::{Error, Response};
-use reqwest::redirect::Policy;
-use crate::BASE_API;
-use crate::mobile_handle::vo::UserData;
+use anyhow::Result;
+use scraper::{Html, Selector};
+use mai_api::music_data::{find_music_id_by_title, get_music_title};
+use mai_api::{get_user_favorite_api, UserFavoriteRequest, FAVORITE_ITEM_KIND_MUSIC};
+use crate::mobile_handle::record::{parse_music_detail_page, parse_music_genre_page, parse_record_page};
+use crate::mobile_handle::session::{MobileResult, MobileSession};
+use crate::mobile_handle::vo::{FavoriteMusic, PlayRecord, UserData};
+use tracing::{instrument, warn};
 
-pub fn get_favorites(){
-    let url = BASE_API.to_owned() +"home/userOption/favorite/musicList";
-    reqwest::get(url);
+/// 收藏的乐曲，优先读取网页，失败时改用标题服务器
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&mobile.user_data().user_id)))]
+pub async fn get_favorites(mobile: &mut MobileSession) -> Result<Vec<FavoriteMusic>> {
+    match get_mobile_favorites(mobile).await {
+        Ok(favorites) => Ok(favorites),
+        Err(e) => {
+            warn!(error = %e, "网页收藏读取失败，改用标题服务器");
+            get_title_server_favorites(&mobile.user_data()).await
+        }
+    }
+}
+
+/// 从 `home/userOption/favorite/musicList` 读取
+pub async fn get_mobile_favorites(mobile: &mut MobileSession) -> MobileResult<Vec<FavoriteMusic>> {
+    let html = mobile.fetch("home/userOption/favorite/musicList").await?;
+    let mut favorites = parse_favorites(&html);
+    for favorite in &mut favorites {
+        favorite.music_id = find_music_id_by_title(&favorite.title, favorite.dx);
+    }
+    Ok(favorites)
+}
 
+/// 从标题服务器 `GetUserFavoriteApi` 读取，只有乐曲 ID，标题从乐曲数据中补全
+pub async fn get_title_server_favorites(user_data: &UserData) -> Result<Vec<FavoriteMusic>> {
+    let request = UserFavoriteRequest {
+        user_id: user_data.user_id.parse()?,
+        item_kind: FAVORITE_ITEM_KIND_MUSIC,
+    };
+    let res = get_user_favorite_api(&request, user_data.user_id.clone()).await?;
+    Ok(res
+        .user_favorite_data
+        .item_id_list
+        .into_iter()
+        .map(|id| FavoriteMusic {
+            music_id: Some(id),
+            title: get_music_title(id).unwrap_or_default(),
+            genre: String::new(),
+            dx: id >= 10000,
+        })
+        .collect())
 }
 
-pub async fn get_records(user_data: UserData){
-    let url = BASE_API.to_owned() +"record";
-    let client = reqwest::Client::builder()
-        .redirect(Policy::none())
-        .build().unwrap();
-    let method = reqwest::Method::GET;
+/// 解析收藏页面，只返回勾选了的乐曲，`music_id` 留空
+pub fn parse_favorites(html: &str) -> Vec<FavoriteMusic> {
+    let document = Html::parse_document(html);
+    let items = Selector::parse(".screw_block, .favorite_music_box").unwrap();
+    let checked = Selector::parse("input[type=checkbox][checked]").unwrap();
+    let name = Selector::parse(".favorite_music_name").unwrap();
+    let kind = Selector::parse("img.music_kind_icon").unwrap();
 
-    let mut req_builder = client.request(method, &url)
-        .header(COOKIE,format!("_t={};userId={}",user_data.session_id,user_data.open_user_id))
-        ;
-   match req_builder.send().await  {
-       Ok(res) => {
-           println!("{:#?}", res);
-       }
-       Err(e) => {}
-   };
+    let mut genre = String::new();
+    let mut favorites = Vec::new();
+    for item in document.select(&items) {
+        if item.value().has_class("screw_block", scraper::CaseSensitivity::CaseSensitive) {
+            genre = item.text().collect::<String>().trim().to_string();
+            continue;
+        }
+        if item.select(&checked).next().is_none() {
+            continue;
+        }
+        let Some(title) = item.select(&name).next() else {
+            continue;
+        };
+        let dx = item
+            .select(&kind)
+            .next()
+            .and_then(|img| img.value().attr("src"))
+            .is_some_and(|src| src.contains("music_dx"));
+        favorites.push(FavoriteMusic {
+            music_id: None,
+            title: title.text().collect::<String>().trim().to_string(),
+            genre: genre.clone(),
+            dx,
+        });
+    }
+    favorites
+}
 
+/// 最近的游玩记录
+#[instrument(skip_all, fields(uid = %mai_api::logging::uid_hash(&mobile.user_data().user_id)))]
+pub async fn get_records(mobile: &mut MobileSession) -> MobileResult<Vec<PlayRecord>> {
+    Ok(parse_record_page(&mobile.fetch("record/").await?))
+}
+
+/// 某个分类下某个难度的最好成绩，`diff` 为 0~4（Basic~Re:Master）或 10（宴会场）
+pub async fn get_music_genre_records(mobile: &mut MobileSession, genre: u32, diff: u32) -> MobileResult<Vec<PlayRecord>> {
+    let path = format!("record/musicGenre/search/?genre={genre}&diff={diff}");
+    Ok(parse_music_genre_page(&mobile.fetch(&path).await?))
+}
 
-}
\ No newline at end of file
+/// 单曲各难度的最好成绩，`idx` 来自分类成绩页
+pub async fn get_music_detail_records(mobile: &mut MobileSession, idx: &str) -> MobileResult<Vec<PlayRecord>> {
+    let path = format!("record/musicDetail/?idx={}", urlencoding(idx));
+    Ok(parse_music_detail_page(&mobile.fetch(&path).await?))
+}
+
+fn urlencoding(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_favorite_music_list() {
+        let html = include_str!("../../tests/fixtures/mobile/favorite_music_list.html");
+        let favorites = parse_favorites(html);
+        assert_eq!(
+            favorites,
+            vec![
+                FavoriteMusic {
+                    music_id: None,
+                    title: "ロキ".to_string(),
+                    genre: "niconico＆ボーカロイド".to_string(),
+                    dx: false,
+                },
+                FavoriteMusic {
+                    music_id: None,
+                    title: "PANDORA PARADOXXX".to_string(),
+                    genre: "maimai".to_string(),
+                    dx: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! 舞萌 DX 网页成绩页面的解析
+//!
+//! - `record`：最近 50 首游玩记录，带第几首和游玩时间
+//! - `record/musicGenre/search/?genre=..&diff=..`：按分类列出的最好成绩
+//! - `record/musicDetail/?idx=..`：单曲各难度的最好成绩
+//!
+//! 网页改版时解析结果会变化，`tests/fixtures/mobile` 下保存了页面快照和对应的解析结果
+
+use scraper::{ElementRef, Html, Selector};
+use crate::mobile_handle::vo::{Difficulty, PlayRecord};
+
+fn selector(s: &str) -> Selector {
+    Selector::parse(s).unwrap()
+}
+
+fn text(element: ElementRef) -> String {
+    element.text().collect::<String>().trim().to_string()
+}
+
+fn select_text(element: ElementRef, s: &str) -> Option<String> {
+    element.select(&selector(s)).next().map(text)
+}
+
+fn select_src<'a>(element: ElementRef<'a>, s: &str) -> Option<&'a str> {
+    element.select(&selector(s)).next().and_then(|img| img.value().attr("src"))
+}
+
+/// `100.5000%` -> 1005000
+fn parse_achievement(text: &str) -> Option<i32> {
+    let text = text.trim().trim_end_matches('%');
+    let (int, frac) = text.split_once('.').unwrap_or((text, "0"));
+    let frac = format!("{frac:0<4}");
+    Some(int.trim().parse::<i32>().ok()? * 10000 + frac.get(..4)?.parse::<i32>().ok()?)
+}
+
+/// `2,345 / 2,412` -> (2345, 2412)
+fn parse_dx_score(text: &str) -> Option<(i32, i32)> {
+    let text = text.replace(',', "");
+    let (score, max) = text.split_once('/')?;
+    Some((score.trim().parse().ok()?, max.trim().parse().ok()?))
+}
+
+enum Badge {
+    Fc(&'static str),
+    Fs(&'static str),
+}
+
+/// 按图标文件名识别 FC / FS 图标，`fc_dummy.png`、`music_icon_back.png` 等空图标返回 `None`
+fn parse_badge(src: &str) -> Option<Badge> {
+    let name = src.rsplit('/').next()?.split(['.', '?']).next()?;
+    let name = name.strip_prefix("music_icon_").unwrap_or(name);
+    Some(match name {
+        "fc" => Badge::Fc("fc"),
+        "fcp" | "fcplus" => Badge::Fc("fcp"),
+        "ap" => Badge::Fc("ap"),
+        "app" | "applus" => Badge::Fc("app"),
+        "fs" => Badge::Fs("fs"),
+        "fsp" | "fsplus" => Badge::Fs("fsp"),
+        "fsd" => Badge::Fs("fsd"),
+        "fsdp" | "fsdplus" => Badge::Fs("fsdp"),
+        "sync" => Badge::Fs("sync"),
+        _ => return None,
+    })
+}
+
+fn parse_badges<'a>(images: impl Iterator<Item = ElementRef<'a>>) -> (Option<String>, Option<String>) {
+    let (mut fc, mut fs) = (None, None);
+    for src in images.filter_map(|img| img.value().attr("src")) {
+        match parse_badge(src) {
+            Some(Badge::Fc(name)) => fc = Some(name.to_string()),
+            Some(Badge::Fs(name)) => fs = Some(name.to_string()),
+            None => {}
+        }
+    }
+    (fc, fs)
+}
+
+/// 游玩记录页 `record`
+pub fn parse_record_page(html: &str) -> Vec<PlayRecord> {
+    let document = Html::parse_document(html);
+    document
+        .select(&selector(".playlog_top_container"))
+        .filter_map(|top| ElementRef::wrap(top.parent()?))
+        .filter_map(parse_playlog)
+        .collect()
+}
+
+fn parse_playlog(block: ElementRef) -> Option<PlayRecord> {
+    let difficulty = Difficulty::from_asset(select_src(block, "img.playlog_diff")?)?;
+    let sub_title: Vec<String> = block.select(&selector(".sub_title span")).map(text).collect();
+    let track = sub_title
+        .iter()
+        .find_map(|s| s.strip_prefix("TRACK"))
+        .and_then(|n| n.trim().parse().ok());
+    let play_time = sub_title.iter().find(|s| !s.starts_with("TRACK")).cloned();
+    let (dx_score, dx_score_max) = parse_dx_score(&select_text(block, ".playlog_score_block")?)?;
+    let (fc, fs) = parse_badges(block.select(&selector(".playlog_result_innerblock img")));
+    Some(PlayRecord {
+        title: select_text(block, ".basic_block")?,
+        difficulty,
+        dx: select_src(block, "img.playlog_music_kind_icon").is_some_and(|src| src.contains("music_dx")),
+        achievement: parse_achievement(&select_text(block, ".playlog_achievement_txt")?)?,
+        dx_score,
+        dx_score_max,
+        fc,
+        fs,
+        play_time,
+        track,
+    })
+}
+
+/// 一个难度的成绩块（`music_master_score_back` 等），没玩过的难度返回 `None`
+fn parse_score_block(block: ElementRef, title: String, dx: bool) -> Option<PlayRecord> {
+    let class = block.value().attr("class")?;
+    let difficulty = Difficulty::from_asset(class.split_whitespace().find(|c| c.ends_with("_score_back"))?)?;
+    let scores: Vec<String> = block.select(&selector(".music_score_block")).map(text).collect();
+    let achievement = parse_achievement(scores.first()?)?;
+    let (dx_score, dx_score_max) = parse_dx_score(scores.get(1)?)?;
+    let (fc, fs) = parse_badges(block.select(&selector("img")));
+    Some(PlayRecord {
+        title,
+        difficulty,
+        dx,
+        achievement,
+        dx_score,
+        dx_score_max,
+        fc,
+        fs,
+        play_time: None,
+        track: None,
+    })
+}
+
+const SCORE_BLOCKS: &str = "div[class*=\"_score_back\"]";
+
+/// 分类成绩页 `record/musicGenre/search/`
+pub fn parse_music_genre_page(html: &str) -> Vec<PlayRecord> {
+    let document = Html::parse_document(html);
+    document
+        .select(&selector(SCORE_BLOCKS))
+        .filter_map(|block| {
+            let title = select_text(block, ".music_name_block")?;
+            // 谱面类型图标在成绩块外面
+            let dx = ElementRef::wrap(block.parent()?)
+                .and_then(|parent| select_src(parent, "img.music_kind_icon"))
+                .is_some_and(|src| src.contains("music_dx"));
+            parse_score_block(block, title, dx)
+        })
+        .collect()
+}
+
+/// 单曲成绩页 `record/musicDetail/`
+pub fn parse_music_detail_page(html: &str) -> Vec<PlayRecord> {
+    let document = Html::parse_document(html);
+    let root = document.root_element();
+    let Some(title) = select_text(root, ".basic_block .f_15") else {
+        return Vec::new();
+    };
+    let dx = select_src(root, ".basic_block img.music_kind_icon").is_some_and(|src| src.contains("music_dx"));
+    document
+        .select(&selector(SCORE_BLOCKS))
+        .filter_map(|block| parse_score_block(block, title.clone(), dx))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// 与 `<name>.snap.json` 比较，设置 `UPDATE_SNAPSHOTS=1` 时重新生成
+    fn assert_snapshot(name: &str, records: &[PlayRecord]) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/mobile/{name}.snap.json"));
+        let actual = serde_json::to_string_pretty(records).unwrap() + "\n";
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(expected == actual, "{name} 解析结果与快照不一致，网页可能改版了\n{actual}");
+    }
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/mobile/{name}.html"));
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn record_page() {
+        let records = parse_record_page(&fixture("record"));
+        assert_eq!(records.len(), 3);
+        assert_snapshot("record", &records);
+    }
+
+    #[test]
+    fn music_genre_page() {
+        let records = parse_music_genre_page(&fixture("music_genre"));
+        assert_eq!(records.len(), 2, "没玩过的谱面不应出现");
+        assert_snapshot("music_genre", &records);
+    }
+
+    #[test]
+    fn music_detail_page() {
+        let records = parse_music_detail_page(&fixture("music_detail"));
+        assert_eq!(records.len(), 3);
+        assert_snapshot("music_detail", &records);
+    }
+
+    #[test]
+    fn achievement_and_dx_score() {
+        assert_eq!(parse_achievement("100.5000%"), Some(1005000));
+        assert_eq!(parse_achievement("97.12%"), Some(971200));
+        assert_eq!(parse_achievement("0%"), Some(0));
+        assert_eq!(parse_dx_score("2,345 / 2,412"), Some((2345, 2412)));
+        assert_eq!(parse_dx_score("―"), None);
+    }
+}
@@ -0,0 +1,270 @@
+//! 带 cookie jar 的舞萌 DX 网页客户端
+//!
+//! 网页每次响应都会下发新的 `_t` cookie，旧的随即失效。[`MobileSession`] 在每次请求后把新的 `_t`
+//! 写回会话存储，同一个会话之后的请求（包括其他请求线程）都会用到最新的值。
+//!
+//! 同一个令牌的请求由 [`SessionLocks`] 串行执行：持锁期间从存储读出最新的 `_t`、请求网页、写回新的 `_t`，
+//! 否则并发的两个请求会带着同一个 `_t` 发出，后到的那个会被重定向到错误页
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::redirect::Policy;
+use reqwest::{StatusCode, Url};
+use proxy::session::{Session, SessionStore};
+use tracing::{debug, warn};
+use crate::BASE_API;
+use crate::mobile_handle::vo::UserData;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MobileError {
+    /// 被重定向到错误页或登录页，需要重新登录
+    #[error("maimai-mobile 会话已失效，请重新登录")]
+    SessionExpired,
+    #[error("maimai-mobile 请求失败: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("maimai-mobile 返回 {0}")]
+    Status(StatusCode),
+    #[error("会话存储失败: {0}")]
+    Store(anyhow::Error),
+}
+
+pub type MobileResult<T> = Result<T, MobileError>;
+
+/// 每个会话令牌一把异步锁
+#[derive(Clone, Default)]
+pub struct SessionLocks {
+    locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+}
+
+impl SessionLocks {
+    pub async fn lock(&self, token: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // 顺便清理没有人持有的锁
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(token.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+pub struct MobileSession {
+    client: reqwest::Client,
+    jar: Arc<Jar>,
+    base: Url,
+    token: String,
+    session: Session,
+    store: Arc<dyn SessionStore>,
+    locks: SessionLocks,
+}
+
+impl MobileSession {
+    pub fn new(store: Arc<dyn SessionStore>, locks: SessionLocks, token: String, session: Session) -> MobileResult<Self> {
+        Self::with_base_url(store, locks, token, session, BASE_API)
+    }
+
+    /// 指定网页根地址，测试时指向本地 mock
+    pub fn with_base_url(
+        store: Arc<dyn SessionStore>,
+        locks: SessionLocks,
+        token: String,
+        session: Session,
+        base: &str,
+    ) -> MobileResult<Self> {
+        let base = Url::parse(base).expect("BASE_API 必须是合法 URL");
+        let jar = Arc::new(Jar::default());
+        for (name, value) in &session.cookies {
+            jar.add_cookie_str(&format!("{name}={value}; Path=/"), &base);
+        }
+        let client = reqwest::Client::builder()
+            .cookie_provider(jar.clone())
+            .redirect(Policy::none())
+            .build()?;
+        Ok(Self { client, jar, base, token, session, store, locks })
+    }
+
+    pub fn user_data(&self) -> UserData {
+        UserData { user_id: self.session.login.user_id.to_string() }
+    }
+
+    /// 请求 `path`（相对网页根地址）并返回 HTML
+    pub async fn fetch(&mut self, path: &str) -> MobileResult<String> {
+        let url = self.base.join(path).map_err(|_| MobileError::Status(StatusCode::BAD_REQUEST))?;
+        let _guard = self.locks.lock(&self.token).await;
+        self.reload().await?;
+        let res = self.client.get(url).send().await?;
+        let status = res.status();
+        debug!(%status, "mobile page response");
+        // 即使是错误页也会下发新的 `_t`，先保存下来
+        self.persist_cookies().await?;
+
+        if status.is_redirection() {
+            let location = res
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if is_login_or_error_page(location) {
+                return Err(MobileError::SessionExpired);
+            }
+            return Err(MobileError::Status(status));
+        }
+        if !status.is_success() {
+            return Err(MobileError::Status(status));
+        }
+        Ok(res.text().await?)
+    }
+
+    /// 从会话存储读出其他请求写回的最新 cookie，会话已被删除时视为失效
+    async fn reload(&mut self) -> MobileResult<()> {
+        let session = self.store.get(&self.token).await.map_err(MobileError::Store)?;
+        self.session = session.ok_or(MobileError::SessionExpired)?;
+        for (name, value) in &self.session.cookies {
+            self.jar.add_cookie_str(&format!("{name}={value}; Path=/"), &self.base);
+        }
+        Ok(())
+    }
+
+    /// 把 cookie jar 中最新的 cookie 写回会话存储
+    async fn persist_cookies(&mut self) -> MobileResult<()> {
+        let Some(header) = self.jar.cookies(&self.base) else {
+            return Ok(());
+        };
+        let mut changed = false;
+        for pair in header.to_str().unwrap_or_default().split(';') {
+            let Some((name, value)) = pair.trim().split_once('=') else {
+                continue;
+            };
+            if self.session.cookies.get(name).map(String::as_str) != Some(value) {
+                self.session.cookies.insert(name.to_string(), value.to_string());
+                changed = true;
+            }
+        }
+        if changed {
+            debug!("mobile cookie rotated");
+            if let Err(e) = self.store.put(&self.token, &self.session).await {
+                warn!(error = %e, "保存新的 _t 失败");
+                return Err(MobileError::Store(e));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 会话失效时网页会重定向到 `error/` 或网页首页（登录页）
+fn is_login_or_error_page(location: &str) -> bool {
+    let path = location.split(['?', '#']).next().unwrap_or_default();
+    path.contains("/error") || path.ends_with("/maimai-mobile/") || path.ends_with("/maimai-mobile")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use axum::http::{header, HeaderMap};
+    use axum::response::{IntoResponse, Redirect};
+    use axum::routing::get;
+    use axum::Router;
+    use proxy::session::{MemorySessionStore, DEFAULT_SESSION_TTL};
+    use proxy::LoginResponse;
+
+    /// 每次请求都检查 `_t` 是否为上一次下发的值并下发新的 `_t`，值不对时重定向到错误页
+    async fn mock_mobile() -> String {
+        let counter = Arc::new(AtomicU32::new(0));
+        let app = Router::new().route(
+            "/maimai-mobile/record/",
+            get(move |headers: HeaderMap| {
+                let counter = counter.clone();
+                async move {
+                    let n = counter.load(Ordering::SeqCst);
+                    let cookie = headers.get(header::COOKIE).and_then(|v| v.to_str().ok()).unwrap_or_default();
+                    if !cookie.split("; ").any(|c| c == format!("_t=t{n}")) {
+                        return Redirect::to("/maimai-mobile/error/").into_response();
+                    }
+                    // 拉长检查和轮换之间的间隔，并发请求不串行时必然撞上
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                    counter.store(n + 1, Ordering::SeqCst);
+                    ([(header::SET_COOKIE, format!("_t=t{}; Path=/", n + 1))], "<html>ok</html>").into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/maimai-mobile/")
+    }
+
+    fn session() -> Session {
+        let login = LoginResponse {
+            error_id: 0,
+            open_game_id: "MAID".to_string(),
+            user_id: 1,
+            session_id: 2,
+            user_play_flag: true,
+            new_user_id_flag: false,
+            open_game_id_flag: true,
+        };
+        let cookies = HashMap::from([("_t".to_string(), "t0".to_string()), ("userId".to_string(), "u".to_string())]);
+        Session::new(login, "u".to_string(), cookies, DEFAULT_SESSION_TTL)
+    }
+
+    #[tokio::test]
+    async fn rotated_token_is_persisted() {
+        let base = mock_mobile().await;
+        let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+        let token = store.create(&session()).await.unwrap();
+
+        let locks = SessionLocks::default();
+        let mut mobile = MobileSession::with_base_url(store.clone(), locks.clone(), token.clone(), session(), &base).unwrap();
+        mobile.fetch("record/").await.unwrap();
+        mobile.fetch("record/").await.unwrap();
+        assert_eq!(store.get(&token).await.unwrap().unwrap().cookies["_t"], "t2");
+
+        // 新请求从存储中取出会话，能接着用
+        let stored = store.get(&token).await.unwrap().unwrap();
+        let mut mobile = MobileSession::with_base_url(store.clone(), locks, token.clone(), stored, &base).unwrap();
+        mobile.fetch("record/").await.unwrap();
+        assert_eq!(store.get(&token).await.unwrap().unwrap().cookies["_t"], "t3");
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_share_rotated_token() {
+        let base = mock_mobile().await;
+        let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+        let token = store.create(&session()).await.unwrap();
+        let locks = SessionLocks::default();
+
+        // 两个请求各自从存储取出同一份会话，都带着 `_t=t0`
+        let mut a = MobileSession::with_base_url(store.clone(), locks.clone(), token.clone(), session(), &base).unwrap();
+        let mut b = MobileSession::with_base_url(store.clone(), locks.clone(), token.clone(), session(), &base).unwrap();
+        let (ra, rb) = tokio::join!(a.fetch("record/"), b.fetch("record/"));
+        ra.unwrap();
+        rb.unwrap();
+        assert_eq!(store.get(&token).await.unwrap().unwrap().cookies["_t"], "t2");
+    }
+
+    #[tokio::test]
+    async fn stale_token_is_session_expired() {
+        let base = mock_mobile().await;
+        let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+        let mut stale = session();
+        stale.cookies.insert("_t".to_string(), "old".to_string());
+        let token = store.create(&stale).await.unwrap();
+        let mut mobile = MobileSession::with_base_url(store.clone(), SessionLocks::default(), token.clone(), stale, &base).unwrap();
+        assert!(matches!(mobile.fetch("record/").await, Err(MobileError::SessionExpired)));
+
+        // 已注销的会话
+        store.remove(&token).await.unwrap();
+        assert!(matches!(mobile.fetch("record/").await, Err(MobileError::SessionExpired)));
+    }
+
+    #[test]
+    fn login_and_error_pages() {
+        assert!(is_login_or_error_page("https://maimai.wahlap.com/maimai-mobile/error/"));
+        assert!(is_login_or_error_page("https://maimai.wahlap.com/maimai-mobile/"));
+        assert!(!is_login_or_error_page("https://maimai.wahlap.com/maimai-mobile/record/"));
+    }
+}
@@ -1,5 +1,66 @@
+use serde::Serialize;
+
 pub struct UserData{
-    pub session_id:String,
-    pub open_user_id:String,
-    pub user_id:String
-}
\ No newline at end of file
+    pub user_id:String,
+}
+
+/// 收藏的乐曲
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FavoriteMusic {
+    /// 在乐曲数据中找不到同名乐曲时为 `None`
+    pub music_id: Option<i32>,
+    pub title: String,
+    /// 网页上的分类，从标题服务器获取时为空
+    pub genre: String,
+    pub dx: bool,
+}
+
+/// 谱面难度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Basic,
+    Advanced,
+    Expert,
+    Master,
+    ReMaster,
+    Utage,
+}
+
+impl Difficulty {
+    /// 从难度图标文件名（`diff_master.png`）或样式名（`music_master_score_back`）中识别
+    pub fn from_asset(name: &str) -> Option<Self> {
+        // remaster 要先于 master 匹配
+        [
+            ("remaster", Difficulty::ReMaster),
+            ("basic", Difficulty::Basic),
+            ("advanced", Difficulty::Advanced),
+            ("expert", Difficulty::Expert),
+            ("master", Difficulty::Master),
+            ("utage", Difficulty::Utage),
+        ]
+        .into_iter()
+        .find(|(key, _)| name.contains(key))
+        .map(|(_, d)| d)
+    }
+}
+
+/// 网页上的一条成绩
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlayRecord {
+    pub title: String,
+    pub difficulty: Difficulty,
+    pub dx: bool,
+    /// 达成率 ×10000，与标题服务器一致
+    pub achievement: i32,
+    pub dx_score: i32,
+    pub dx_score_max: i32,
+    /// `fc` / `fcp` / `ap` / `app`
+    pub fc: Option<String>,
+    /// `fs` / `fsp` / `fsd` / `fsdp` / `sync`
+    pub fs: Option<String>,
+    /// 游玩时间，只有游玩记录页有
+    pub play_time: Option<String>,
+    /// 第几首，只有游玩记录页有
+    pub track: Option<u8>,
+}
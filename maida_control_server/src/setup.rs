@@ -0,0 +1,150 @@
+//! 手机代理设置
+//!
+//! | 路由 | 返回 |
+//! |---|---|
+//! | `GET /setup` | 设置说明页 |
+//! | `GET /setup/proxy.pac` | PAC 文件，只有登录主机和自检地址经过代理 |
+//! | `GET /setup/ca.crt` | DER 格式的 CA 证书，iOS / Android 点开即可安装 |
+//! | `GET /setup/ca.pem` | PEM 格式的 CA 证书 |
+//! | `GET /setup/check` | 请求是否经过代理（`{"viaProxy": bool}`） |
+//!
+//! PAC 中的代理地址取自访问本服务时的主机名加上代理端口，手机需要用局域网地址打开设置页
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use tracing::warn;
+use crate::api::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/setup", get(setup_page))
+        .route("/setup/proxy.pac", get(proxy_pac))
+        .route("/setup/ca.crt", get(ca_der))
+        .route("/setup/ca.pem", get(ca_pem))
+        .route(proxy::SETUP_CHECK_PATH, get(check))
+}
+
+/// 请求中的 `Host`，没有时退回监听地址
+fn request_host(headers: &HeaderMap, state: &AppState) -> String {
+    headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| state.proxy.api_listen.to_string())
+}
+
+/// 去掉端口，兼容 `[::1]:9855` 形式的 IPv6 地址
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+async fn proxy_pac(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let host = request_host(&headers, &state);
+    let proxy_addr = format!("{}:{}", strip_port(&host), state.proxy.listen.port());
+    let check_url = format!("http://{host}{}", proxy::SETUP_CHECK_PATH);
+    let pac = proxy::pac::generate(&state.proxy, &proxy_addr, &check_url);
+    ([(header::CONTENT_TYPE, proxy::pac::PAC_MIME), (header::CACHE_CONTROL, "no-cache")], pac).into_response()
+}
+
+async fn ca_der(State(state): State<AppState>) -> Response {
+    match proxy::tls::load_ca_der(&state.proxy.ca_cert_path) {
+        Ok(der) => (
+            [
+                (header::CONTENT_TYPE, "application/x-x509-ca-cert"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"maida-ca.crt\""),
+            ],
+            der,
+        )
+            .into_response(),
+        Err(e) => ca_unavailable(e),
+    }
+}
+
+async fn ca_pem(State(state): State<AppState>) -> Response {
+    match std::fs::read(&state.proxy.ca_cert_path) {
+        Ok(pem) => (
+            [
+                (header::CONTENT_TYPE, "application/x-pem-file"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"maida-ca.pem\""),
+            ],
+            pem,
+        )
+            .into_response(),
+        Err(e) => ca_unavailable(e.into()),
+    }
+}
+
+fn ca_unavailable(e: anyhow::Error) -> Response {
+    warn!(error = ?e, "CA 证书读取失败");
+    (StatusCode::INTERNAL_SERVER_ERROR, "CA 证书不可用").into_response()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckResponse {
+    via_proxy: bool,
+}
+
+/// 代理转发时会带上 `Via: 1.1 maida-proxy`
+async fn check(headers: HeaderMap) -> Json<CheckResponse> {
+    let via_proxy = headers
+        .get_all(header::VIA)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim() == proxy::VIA);
+    Json(CheckResponse { via_proxy })
+}
+
+async fn setup_page(State(state): State<AppState>, headers: HeaderMap) -> Html<String> {
+    let host = request_host(&headers, &state);
+    Html(format!(
+        r#"<!doctype html>
+<html lang="zh-CN">
+<head>
+<meta charset="utf-8" />
+<meta name="viewport" content="width=device-width, initial-scale=1" />
+<title>MaiDaControl 代理设置</title>
+</head>
+<body>
+<h1>MaiDaControl 代理设置</h1>
+<ol>
+  <li>下载并安装 CA 证书：<a href="/setup/ca.crt">maida-ca.crt</a>（<a href="/setup/ca.pem">PEM 格式</a>）
+    <ul>
+      <li>iOS：设置 → 已下载的描述文件 → 安装，再到 通用 → 关于本机 → 证书信任设置 打开完全信任</li>
+      <li>Android：设置 → 安全 → 加密与凭据 → 安装证书 → CA 证书</li>
+    </ul>
+  </li>
+  <li>Wi-Fi 代理选择“自动”，URL 填写 <code>http://{host}/setup/proxy.pac</code></li>
+  <li><a href="/setup/check">检查代理是否生效</a>，显示 <code>"viaProxy":true</code> 即可</li>
+</ol>
+</body>
+</html>
+"#,
+        host = html_escape(&host),
+    ))
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_are_stripped() {
+        assert_eq!(strip_port("192.168.10.9:9855"), "192.168.10.9");
+        assert_eq!(strip_port("maida.example.com"), "maida.example.com");
+        assert_eq!(strip_port("[::1]:9855"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+}
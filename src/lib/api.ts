@@ -1,9 +1,20 @@
-import {dxNet} from "@/lib/request";
+import {api} from "@/lib/request";
 
+export function getOwnHomeData(){
+    return api.get("/v1/me/preview")
+}
+export function getRecords(){
+    return api.get("/v1/me/records")
+}
 export function getFavorites(){
-    return dxNet("Favorites")
+    return api.get("/v1/me/favorites")
 }
-export function getOwnHomeData(){
-    return dxNet("OwnHomeData")
+export function getRating(){
+    return api.get("/v1/me/rating")
+}
+export function getRatingTargets(gain = 1, limit = 50){
+    return api.get("/v1/me/rating/targets", {params: {gain, limit}})
+}
+export function logout(){
+    return api.delete("/v1/session")
 }
-
@@ -1,19 +1,25 @@
 import axios from 'axios';
 
 const api = axios.create({
-    baseURL: 'http://192.168.10.9:9855', // API 基础地址
-    headers: {
-        'X-User-Id': localStorage.getItem('user_id'),
-        'X-Session-Id': localStorage.getItem('session_id'),
-        'X-Open-User-Id': localStorage.getItem('open_user_id')
+    // API 基础地址，默认走 vite 的 /mc 代理；前后端分开部署时用 VITE_API_BASE 指定
+    baseURL: import.meta.env.VITE_API_BASE ?? '/mc',
+});
+
+// 每次请求时读取，登录后写入的令牌无需刷新页面即可生效
+api.interceptors.request.use(config => {
+    const token = localStorage.getItem('session_token');
+    if (token) {
+        config.headers.Authorization = `Bearer ${token}`;
+    }
+    return config;
+});
+
+api.interceptors.response.use(res => res, err => {
+    // 会话过期或被注销
+    if (err.response?.status === 401) {
+        localStorage.removeItem('session_token');
     }
+    return Promise.reject(err);
 });
 
-const dxNet = (method)=>{
-    return api.get("/api",{
-        headers:{
-            "method":method
-        }
-    })
-};
-export {api, dxNet};
\ No newline at end of file
+export {api};
\ No newline at end of file